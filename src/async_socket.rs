@@ -1,15 +1,26 @@
-use anyhow::{Context, Result};
+pub use crate::protocol::{
+    CAP_PERSISTENT, ConnectionClosed, DEFAULT_TIMEOUT, Frame, IncompatibleProtocol,
+    MAX_MESSAGE_SIZE, PROTOCOL_VERSION, Request,
+};
+use crate::protocol::{Hello, decode, encode, io_error, message_length, negotiate};
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use std::{
+    collections::VecDeque,
+    fmt::{self, Display},
     fs,
+    marker::PhantomData,
     os::unix::fs::PermissionsExt,
     path::{Path, PathBuf},
+    time::Duration,
 };
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
     net::{UnixListener, UnixStream},
+    sync::mpsc,
 };
 
-async fn create_socket_listener(path: &Path, set_permissions: bool) -> Result<UnixListener> {
+async fn create_socket_listener(path: &Path, mode: u32) -> Result<UnixListener> {
     if let Some(run_dir) = path.parent() {
         fs::create_dir_all(run_dir)
             .with_context(|| format!("failed to create runtime directory '{run_dir:?}'"))?;
@@ -20,10 +31,8 @@ async fn create_socket_listener(path: &Path, set_permissions: bool) -> Result<Un
     }
     let listener = tokio::net::UnixListener::bind(path)
         .with_context(|| format!("failed to bind socket at '{path:?}'"))?;
-    if set_permissions {
-        // set Unix permissions such that all users can write to the socket
-        fs::set_permissions(path, fs::Permissions::from_mode(0o722)).unwrap();
-    }
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+        .with_context(|| format!("failed to set permissions of socket '{path:?}'"))?;
     log::info!("created at socket at '{}'", path.display());
     Ok(listener)
 }
@@ -35,14 +44,58 @@ async fn create_socket_stream(path: PathBuf) -> Result<UnixStream> {
     Ok(stream)
 }
 
+/// Used to answer requests of which the body could not be decoded
+#[derive(Deserialize)]
+struct RequestId {
+    id: u64,
+}
+
+/// Credentials of the process on the other side of a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerCred {
+    pub uid: u32,
+    pub gid: u32,
+    pub pid: Option<i32>,
+}
+
+impl PeerCred {
+    fn of(stream: &UnixStream) -> Result<Self> {
+        let cred = stream
+            .peer_cred()
+            .context("failed to read peer credentials")?;
+        Ok(Self {
+            uid: cred.uid(),
+            gid: cred.gid(),
+            pid: cred.pid(),
+        })
+    }
+
+    /// Whether the peer runs as the same user as this process
+    pub fn is_current_user(&self) -> bool {
+        // SAFETY: geteuid is always successful
+        self.uid == unsafe { libc::geteuid() }
+    }
+}
+
+impl Display for PeerCred {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "uid {} gid {}", self.uid, self.gid)?;
+        if let Some(pid) = self.pid {
+            write!(f, " pid {pid}")?;
+        }
+        Ok(())
+    }
+}
+
 pub struct SocketServer {
     listener: UnixListener,
     path: PathBuf,
 }
 
 impl SocketServer {
-    pub async fn create(path: PathBuf, set_permissions: bool) -> Result<Self> {
-        let listener = create_socket_listener(&path, set_permissions).await?;
+    /// Create a socket at `path` with the given Unix permissions, replacing an existing one
+    pub async fn create(path: PathBuf, mode: u32) -> Result<Self> {
+        let listener = create_socket_listener(&path, mode).await?;
         Ok(Self { listener, path })
     }
 
@@ -52,51 +105,101 @@ impl SocketServer {
 
     pub async fn accept_client(&mut self) -> Result<SocketStream> {
         let (stream, _) = self.listener.accept().await?;
-        Ok(SocketStream { stream })
+        Ok(SocketStream {
+            peer: PeerCred::of(&stream)?,
+            stream,
+            capabilities: Vec::new(),
+            timeout: DEFAULT_TIMEOUT,
+        })
     }
 }
 
 pub struct SocketStream {
     stream: UnixStream,
+    peer: PeerCred,
+    capabilities: Vec<String>,
+    timeout: Duration,
 }
 
 impl SocketStream {
-    pub async fn connect(path: PathBuf) -> Result<Self> {
-        Ok(Self {
-            stream: create_socket_stream(path).await?,
-        })
+    /// Connect and exchange hellos, offering the given capabilities
+    pub async fn connect(path: PathBuf, capabilities: &[&str]) -> Result<Self> {
+        let stream = create_socket_stream(path).await?;
+        let mut stream = Self {
+            peer: PeerCred::of(&stream)?,
+            stream,
+            capabilities: Vec::new(),
+            timeout: DEFAULT_TIMEOUT,
+        };
+        stream.send(Hello::new(capabilities)).await?;
+        let hello: Hello = stream
+            .recv_timeout(stream.timeout)
+            .await
+            .context("no protocol hello received, the server may use an older protocol")?;
+        if hello.version != PROTOCOL_VERSION {
+            return Err(IncompatibleProtocol {
+                version: hello.version,
+            }
+            .into());
+        }
+        stream.capabilities = negotiate(capabilities, &hello.capabilities);
+        Ok(stream)
     }
 
-    pub async fn send<T: for<'a> serde::Serialize>(&mut self, msg: T) -> Result<()> {
-        let bytes = rmp_serde::to_vec(&msg).with_context(|| "failed to serialize message")?;
-        self.stream
-            .write_u32(bytes.len() as u32)
-            .await
-            .context("failed to write message length")?;
-        self.stream
-            .write_all(&bytes)
+    /// Complete the hello exchange of an accepted client, offering the given capabilities
+    pub async fn accept_hello(&mut self, capabilities: &[&str]) -> Result<()> {
+        let hello: Hello = self
+            .recv_timeout(self.timeout)
             .await
-            .context("failed to write message")?;
-        self.stream
-            .flush()
-            .await
-            .context("failed to flush stream")?;
+            .context("no protocol hello received, the client may use an older protocol")?;
+        // always reply, such that the client can report the version mismatch
+        self.send(Hello::new(capabilities)).await?;
+        if hello.version != PROTOCOL_VERSION {
+            return Err(IncompatibleProtocol {
+                version: hello.version,
+            }
+            .into());
+        }
+        self.capabilities = negotiate(capabilities, &hello.capabilities);
         Ok(())
     }
 
+    /// Credentials of the process on the other side, read when the connection was made
+    pub fn peer(&self) -> PeerCred {
+        self.peer
+    }
+
+    /// Whether both sides of the connection support a capability
+    pub fn has_capability(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+
+    /// Set the time to write a message, or to read the rest of a message once it started
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    pub async fn send<T: for<'a> serde::Serialize>(&mut self, msg: T) -> Result<()> {
+        write_message(&mut self.stream, &encode(&msg)?, self.timeout).await
+    }
+
+    /// Wait for the next message, returns [`ConnectionClosed`] if the peer closed the connection
     pub async fn recv<T: for<'a> serde::Deserialize<'a>>(&mut self) -> Result<T> {
-        let length = self
-            .stream
-            .read_u32()
-            .await
-            .context("failed to read message length")?;
-        let mut buf = vec![0; length as usize];
-        self.stream
-            .read_exact(&mut buf)
+        decode(&read_message(&mut self.stream, self.timeout).await?)
+    }
+
+    /// Receive a message, failing if it does not arrive within the timeout
+    pub async fn recv_timeout<T: for<'a> serde::Deserialize<'a>>(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<T> {
+        tokio::time::timeout(timeout, self.recv())
             .await
-            .context("failed to read message")?;
-        let msg: T = rmp_serde::from_slice(&buf).context("failed to deserialize message")?;
-        Ok(msg)
+            .context("timed out waiting for message")?
     }
 
     pub async fn send_and_recv<D, S>(&mut self, msg: S) -> Result<D>
@@ -105,6 +208,163 @@ impl SocketStream {
         S: for<'a> serde::Serialize,
     {
         self.send(msg).await?;
-        self.recv().await
+        self.recv_timeout(self.timeout).await
+    }
+
+    /// Serve requests on a persistent connection until the client disconnects
+    ///
+    /// Requests are answered in order by `handler`, which also receives the requests that could
+    /// not be decoded such that it can report the error. Messages received on `notifications`
+    /// are pushed to the client in between responses
+    pub async fn serve<Req, Resp, F, Fut>(
+        self,
+        mut notifications: mpsc::Receiver<Resp>,
+        mut handler: F,
+    ) -> Result<()>
+    where
+        Req: DeserializeOwned,
+        Resp: Serialize,
+        F: FnMut(Result<Req>) -> Fut,
+        Fut: Future<Output = Resp>,
+    {
+        let timeout = self.timeout;
+        let (mut reader, mut writer) = self.stream.into_split();
+        // reading is not cancel safe, so requests are read by a separate task
+        let (request_tx, mut request_rx) = mpsc::channel(16);
+        let read_task = tokio::spawn(async move {
+            loop {
+                let message = read_message(&mut reader, timeout).await;
+                let failed = message.is_err();
+                if request_tx.send(message).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+        let result: Result<()> = async {
+            loop {
+                let frame = tokio::select! {
+                    Some(message) = request_rx.recv() => {
+                        let message = message?;
+                        let RequestId { id } = decode(&message)?;
+                        let request = decode::<Request<Req>>(&message).map(|r| r.body);
+                        Frame::Response { id, body: handler(request).await }
+                    }
+                    Some(notification) = notifications.recv() => Frame::Notification(notification),
+                    else => return Ok(()),
+                };
+                write_message(&mut writer, &encode(&frame)?, timeout).await?;
+            }
+        }
+        .await;
+        read_task.abort();
+        result
+    }
+}
+
+/// Client side of a persistent connection, see [`SocketStream::serve`]
+pub struct Connection<Req, Resp> {
+    stream: SocketStream,
+    next_id: u64,
+    /// Frames received while waiting for a different response
+    pending: VecDeque<Frame<Resp>>,
+    _request: PhantomData<fn(Req)>,
+}
+
+impl<Req: Serialize, Resp: DeserializeOwned> Connection<Req, Resp> {
+    /// Use a stream on which [`CAP_PERSISTENT`] was negotiated
+    pub fn new(stream: SocketStream) -> Result<Self> {
+        if !stream.has_capability(CAP_PERSISTENT) {
+            bail!("the server does not support persistent connections");
+        }
+        Ok(Self {
+            stream,
+            next_id: 0,
+            pending: VecDeque::new(),
+            _request: PhantomData,
+        })
+    }
+
+    pub fn stream(&self) -> &SocketStream {
+        &self.stream
+    }
+
+    /// Send a request without waiting for the response, returns the id of the request
+    pub async fn send(&mut self, body: Req) -> Result<u64> {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.stream.send(Request { id, body }).await?;
+        Ok(id)
     }
+
+    /// Wait for the response to a request, other frames received meanwhile are kept for [`Self::recv`]
+    pub async fn response(&mut self, id: u64) -> Result<Resp> {
+        let position = self
+            .pending
+            .iter()
+            .position(|f| matches!(f, Frame::Response { id: i, .. } if *i == id));
+        if let Some(Frame::Response { body, .. }) = position.and_then(|p| self.pending.remove(p)) {
+            return Ok(body);
+        }
+        loop {
+            match self.stream.recv_timeout(self.stream.timeout).await? {
+                Frame::Response { id: i, body } if i == id => return Ok(body),
+                frame => self.pending.push_back(frame),
+            }
+        }
+    }
+
+    /// Send a request and wait for its response
+    pub async fn request(&mut self, body: Req) -> Result<Resp> {
+        let id = self.send(body).await?;
+        self.response(id).await
+    }
+
+    /// Wait for the next frame, this is not cancel safe
+    pub async fn recv(&mut self) -> Result<Frame<Resp>> {
+        match self.pending.pop_front() {
+            Some(frame) => Ok(frame),
+            None => self.stream.recv().await,
+        }
+    }
+}
+
+async fn write_message<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    bytes: &[u8],
+    timeout: Duration,
+) -> Result<()> {
+    let length = message_length(bytes)?;
+    tokio::time::timeout(timeout, async {
+        writer
+            .write_u32(length)
+            .await
+            .map_err(|e| io_error(e, "failed to write message length"))?;
+        writer
+            .write_all(bytes)
+            .await
+            .map_err(|e| io_error(e, "failed to write message"))?;
+        writer
+            .flush()
+            .await
+            .map_err(|e| io_error(e, "failed to flush stream"))
+    })
+    .await
+    .context("timed out writing message")?
+}
+
+/// Wait for the length of the next message, then read it within the timeout
+async fn read_message<R: AsyncRead + Unpin>(reader: &mut R, timeout: Duration) -> Result<Vec<u8>> {
+    let length = reader
+        .read_u32()
+        .await
+        .map_err(|e| io_error(e, "failed to read message length"))?;
+    if length > MAX_MESSAGE_SIZE {
+        bail!("message of {length} bytes is too large");
+    }
+    let mut buf = vec![0; length as usize];
+    tokio::time::timeout(timeout, reader.read_exact(&mut buf))
+        .await
+        .context("timed out reading message")?
+        .map_err(|e| io_error(e, "failed to read message"))?;
+    Ok(buf)
 }
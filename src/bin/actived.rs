@@ -3,7 +3,7 @@
 use anyhow::{Context, Result, bail};
 use evdev::{Device, EventType};
 use std::{
-    process,
+    fs, process,
     sync::{
         Arc,
         atomic::{AtomicU64, Ordering},
@@ -12,11 +12,55 @@ use std::{
 use tokio::sync::broadcast;
 use tokio_stream::{StreamExt, StreamMap};
 use ttd::{
-    ActivityMessage, activity_daemon_socket,
-    async_socket::{SocketServer, SocketStream},
+    ActivityMessage, activity_daemon_config, activity_daemon_socket,
+    async_socket::{ConnectionClosed, PeerCred, SocketServer, SocketStream},
     get_unix_time,
 };
 
+/// Which users may subscribe to input events, everyone is allowed when both lists are empty
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    #[serde(default)]
+    allowed_uids: Vec<u32>,
+    /// Matched against the primary and supplementary groups of the client
+    #[serde(default)]
+    allowed_gids: Vec<u32>,
+}
+
+impl Config {
+    fn load() -> Result<Self> {
+        let path = activity_daemon_config();
+        if path.exists() {
+            let config_string = fs::read_to_string(&path)
+                .with_context(|| format!("failed to read config file '{}'", path.display()))?;
+            toml::from_str(&config_string).context("failed to parse config file")
+        } else {
+            log::info!("no config file found, all users may connect");
+            Ok(Config::default())
+        }
+    }
+
+    fn is_allowed(&self, peer: &PeerCred) -> bool {
+        if peer.uid == 0 || (self.allowed_uids.is_empty() && self.allowed_gids.is_empty()) {
+            return true;
+        }
+        if self.allowed_uids.contains(&peer.uid) || self.allowed_gids.contains(&peer.gid) {
+            return true;
+        }
+        peer.pid
+            .and_then(supplementary_groups)
+            .is_some_and(|groups| groups.iter().any(|g| self.allowed_gids.contains(g)))
+    }
+}
+
+/// Supplementary groups of a process, as listed in `/proc/<pid>/status`
+fn supplementary_groups(pid: i32) -> Option<Vec<u32>> {
+    let status = fs::read_to_string(format!("/proc/{pid}/status")).ok()?;
+    let groups = status.lines().find_map(|l| l.strip_prefix("Groups:"))?;
+    groups.split_whitespace().map(|g| g.parse().ok()).collect()
+}
+
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<()> {
     env_logger::builder()
@@ -24,6 +68,8 @@ async fn main() -> Result<()> {
         .parse_default_env()
         .init();
 
+    let config = Config::load()?;
+
     // Broadcast channel for distributing events to all clients
     let (broadcast_tx, _) = broadcast::channel::<u64>(100);
 
@@ -43,7 +89,7 @@ async fn main() -> Result<()> {
         }
     });
 
-    let mut socket_server = SocketServer::create(activity_daemon_socket(), true)
+    let mut socket_server = SocketServer::create(activity_daemon_socket(), 0o666)
         .await
         .context("failed to create socket server")?;
     log::info!("listening for client connections");
@@ -51,13 +97,19 @@ async fn main() -> Result<()> {
     // Accept and handle clients
     loop {
         let stream = socket_server.accept_client().await?;
+        if !config.is_allowed(&stream.peer()) {
+            log::warn!("rejected connection from {}", stream.peer());
+            continue;
+        }
         let broadcast_rx = broadcast_tx.subscribe();
         let last_input = last_input.clone();
 
         // Spawn a new task for each client
         tokio::spawn(async move {
-            if let Err(e) = handle_client(stream, broadcast_rx, last_input).await {
-                log::error!("client handler error: {e:?}");
+            match handle_client(stream, broadcast_rx, last_input).await {
+                Err(e) if e.is::<ConnectionClosed>() => log::debug!("client disconnected"),
+                Err(e) => log::error!("client handler error: {e:?}"),
+                Ok(()) => {}
             }
         });
     }
@@ -68,6 +120,8 @@ async fn handle_client(
     mut broadcast_rx: broadcast::Receiver<u64>,
     last_input: Arc<AtomicU64>,
 ) -> Result<()> {
+    stream.accept_hello(&[]).await?;
+
     // send initial value to the client
     let timestamp = last_input.load(Ordering::Relaxed);
     stream
@@ -1,8 +1,24 @@
-use anyhow::Result;
-use clap::{Parser, command};
-use jiff::{SignedDuration, Timestamp, Zoned, tz::TimeZone};
-use std::collections::BTreeMap;
-use ttd::{Activity, ActivityRead, Event, IpcRequest, IpcResponse, async_socket::SocketStream};
+use anyhow::{Context, Result, bail};
+use clap::Parser;
+use jiff::{
+    SignedDuration, Timestamp, ToSpan, Zoned,
+    civil::{Date, Weekday},
+    tz::TimeZone,
+};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, IsTerminal, Write},
+    time::{Duration, Instant},
+};
+use tokio::sync::mpsc;
+use ttd::{
+    Activity, ActivityInfo, ActivityRead, DisplayDuration, Notification, Presence, Status,
+    client::Client,
+    config_path,
+    search::{Match, find_activity},
+    timeline::{ActivityFilter, Timeline},
+};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -11,8 +27,18 @@ async fn main() -> Result<()> {
         .parse_default_env()
         .init();
     let args = Args::parse();
-    Client::connect().await?.run(args.cmd).await?;
-    Ok(())
+    let format = if args.json { Format::Json } else { args.format };
+    // commands that only read the time log do not need the daemon
+    match args.cmd {
+        Command::Stats {
+            filter,
+            period,
+            depth,
+        } => stats(filter.as_ref(), &period, depth.map(|d| d as usize), format),
+        Command::Log { period } => show_log(&period, format),
+        Command::Export { filter, period } => export(filter.as_ref(), &period, format),
+        cmd => run(Client::connect().await?, format, cmd).await,
+    }
 }
 
 #[derive(Parser, Debug)]
@@ -20,6 +46,12 @@ async fn main() -> Result<()> {
 pub struct Args {
     #[command(subcommand)]
     pub cmd: Command,
+    /// Output format
+    #[arg(long, global = true, value_enum, default_value_t)]
+    pub format: Format,
+    /// Shorthand for '--format json'
+    #[arg(long, global = true, conflicts_with = "format")]
+    pub json: bool,
 }
 
 #[derive(Debug, Clone, clap::Subcommand)]
@@ -28,125 +60,1022 @@ pub enum Command {
     Status,
     /// List all available activities
     List,
-    /// Switch to a new activity
+    /// Switch to a new activity, by its key, an alias, or a part of either
     Switch { activity: String },
     /// Stop tracking the current activity
     Stop,
-    /// Get stattistics
-    Stats,
+    /// Add an activity to the config, or restore an archived one
+    Add { activity: String },
+    /// Remove an activity from the config
+    Remove { activity: String },
+    /// Hide an activity from the list, while keeping it in the config
+    Archive { activity: String },
+    /// Make the daemon load its config file again
+    Reload,
+    /// Print the current activity on every change and every second
+    Watch {
+        /// Output template, with the placeholders {status}, {activity}, {duration} and {idle}
+        #[arg(long, default_value = DEFAULT_TEMPLATE)]
+        template: String,
+        /// Seconds between updates
+        #[arg(long, default_value_t = 1)]
+        interval: u64,
+    },
+    /// Stop the current activity, or resume the last one when stopped
+    Toggle,
+    /// Output for status bars, clicking toggles the current activity
+    Bar {
+        #[arg(value_enum)]
+        kind: BarKind,
+        /// Keep running and print a line on every change and every second, for i3blocks with
+        /// 'interval=persist' clicks are read from stdin
+        #[arg(long)]
+        watch: bool,
+        /// Daily goal in minutes, shown as a percentage of the time tracked today
+        #[arg(long)]
+        goal: Option<u64>,
+        /// Template when tracking, with the placeholders of 'watch' and {percentage}
+        #[arg(long, default_value = "{activity} {duration}")]
+        template: String,
+        /// Text when not tracking an activity
+        #[arg(long, default_value = "(no activity)")]
+        stopped: String,
+    },
+    /// Get stattistics, parents include the time of their children
+    Stats {
+        /// Only this activity, or a parent and its children with e.g. 'work:*'
+        filter: Option<ActivityFilter>,
+        #[command(flatten)]
+        period: Period,
+        /// Only show totals up to this many levels, e.g. 1 for 'work' but not 'work:ttd'
+        #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+        depth: Option<u64>,
+    },
+    /// Show the raw events in the time log
+    Log {
+        #[command(flatten)]
+        period: Period,
+    },
+    /// Export all sessions, as CSV unless another format is given
+    Export {
+        /// Only this activity, or a parent and its children with e.g. 'work:*'
+        filter: Option<ActivityFilter>,
+        #[command(flatten)]
+        period: Period,
+    },
 }
 
-struct Client {
-    stream: SocketStream,
+/// The days to include, defaults to today
+#[derive(Debug, Clone, clap::Args)]
+pub struct Period {
+    /// First day, e.g. '2025-05-01', 'yesterday', 'last monday' or '3 days ago'
+    #[arg(long, conflicts_with_all = ["yesterday", "week", "month", "last"])]
+    from: Option<String>,
+    /// Last day (inclusive), defaults to today
+    #[arg(long, requires = "from")]
+    to: Option<String>,
+    /// Only yesterday
+    #[arg(long, conflicts_with_all = ["week", "month", "last"])]
+    yesterday: bool,
+    /// The current week, starting on Monday
+    #[arg(long, conflicts_with_all = ["month", "last"])]
+    week: bool,
+    /// The current month
+    #[arg(long, conflicts_with = "last")]
+    month: bool,
+    /// The last N days, including today
+    #[arg(long, value_name = "N")]
+    last: Option<u32>,
 }
 
-impl Client {
-    async fn connect() -> Result<Self> {
-        let stream = SocketStream::connect(ttd::socket_path()).await?;
-        Ok(Self { stream })
+impl Period {
+    /// Resolve to the first and last day of the period
+    fn resolve(&self, today: Date) -> Result<(Date, Date)> {
+        Ok(if let Some(from) = &self.from {
+            let from = parse_date(from, today)?;
+            let to = match &self.to {
+                Some(to) => parse_date(to, today)?,
+                None => today,
+            };
+            if to < from {
+                bail!("end date {to} is before start date {from}");
+            }
+            (from, to)
+        } else if self.yesterday {
+            let yesterday = today.yesterday()?;
+            (yesterday, yesterday)
+        } else if self.week {
+            let offset = today.weekday().to_monday_zero_offset();
+            (today.checked_sub(i64::from(offset).days())?, today)
+        } else if self.month {
+            (today.first_of_month(), today)
+        } else if let Some(days) = self.last {
+            if days == 0 {
+                bail!("--last must be at least 1 day");
+            }
+            (today.checked_sub(i64::from(days - 1).days())?, today)
+        } else {
+            (today, today)
+        })
     }
 
-    async fn run(&mut self, cmd: Command) -> Result<()> {
-        match cmd {
-            Command::List => {
-                if let IpcResponse::Activities(activities) =
-                    self.stream.send_and_recv(IpcRequest::GetActivities).await?
-                {
-                    for activity in activities {
-                        println!("{}", activity);
-                    }
+    /// Resolve to the first and last day and the window `[start, end)` they span
+    fn window(&self, today: Date, tz: &TimeZone) -> Result<(Date, Date, i64, i64)> {
+        let (first, last) = self.resolve(today)?;
+        let start = first.to_zoned(tz.clone())?.timestamp().as_second();
+        let end = last
+            .tomorrow()?
+            .to_zoned(tz.clone())?
+            .timestamp()
+            .as_second();
+        Ok((first, last, start, end))
+    }
+}
+
+/// Parse an absolute (`2025-05-01`) or relative (`yesterday`, `last monday`, `3 days ago`) date
+fn parse_date(s: &str, today: Date) -> Result<Date> {
+    let s = s.trim().to_lowercase();
+    match s.as_str() {
+        "today" => return Ok(today),
+        "yesterday" => return Ok(today.yesterday()?),
+        _ => {}
+    }
+    if let Some(weekday) = s.strip_prefix("last ").and_then(parse_weekday) {
+        // the most recent such day before today
+        return Ok(today.nth_weekday(-1, weekday)?);
+    }
+    if let Some(weekday) = parse_weekday(&s) {
+        // the most recent such day, today included
+        return Ok(today.tomorrow()?.nth_weekday(-1, weekday)?);
+    }
+    if let Some(days) = s
+        .strip_suffix(" days ago")
+        .or_else(|| s.strip_suffix(" day ago"))
+    {
+        let days: i64 = days.trim().parse().context("invalid number of days")?;
+        return Ok(today.checked_sub(days.days())?);
+    }
+    s.parse().with_context(|| format!("invalid date: '{s}'"))
+}
+
+fn parse_weekday(s: &str) -> Option<Weekday> {
+    Some(match s {
+        "monday" | "mon" => Weekday::Monday,
+        "tuesday" | "tue" => Weekday::Tuesday,
+        "wednesday" | "wed" => Weekday::Wednesday,
+        "thursday" | "thu" => Weekday::Thursday,
+        "friday" | "fri" => Weekday::Friday,
+        "saturday" | "sat" => Weekday::Saturday,
+        "sunday" | "sun" => Weekday::Sunday,
+        _ => return None,
+    })
+}
+
+/// Call `update` on every notification and in between every interval, with whether
+/// the status was just received from the daemon
+/// Clicking toggles the current activity when `clicks` is given, see [`read_clicks`]
+async fn watch_status(
+    client: &mut Client,
+    interval: Duration,
+    mut clicks: Option<mpsc::Receiver<u64>>,
+    mut update: impl FnMut(&Status, bool) -> Result<()>,
+) -> Result<()> {
+    let mut subscription = client.subscribe().await?;
+    let mut status = None;
+    let mut received = Instant::now();
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        let changed = tokio::select! {
+            update = subscription.next() => {
+                let update = update?;
+                if let Some(Notification::ConfigError(e)) = &update.notification {
+                    log::warn!("the daemon failed to reload its config: {e}");
                 }
+                status = Some(update.status);
+                received = Instant::now();
+                ticker.reset();
+                true
             }
-            Command::Switch { activity } => {
-                if !matches!(
-                    self.stream
-                        .send_and_recv(IpcRequest::Switch(Some(Activity::new(activity)?)))
-                        .await?,
-                    IpcResponse::Empty
-                ) {
-                    eprintln!("unexpected response from server");
+            _ = ticker.tick() => false,
+            Some(button) = async {
+                match &mut clicks {
+                    Some(clicks) => clicks.recv().await,
+                    None => std::future::pending().await,
                 }
-            }
-            Command::Status => {
-                if let IpcResponse::Status(status) =
-                    self.stream.send_and_recv(IpcRequest::Status).await?
+            } => {
+                // the new status is pushed on the subscription
+                if button == 1
+                    && let Err(e) = toggle(client).await
                 {
-                    println!("{status}");
+                    log::error!("{e:#}");
                 }
+                continue;
             }
-            Command::Stop => {
-                if !matches!(
-                    self.stream.send_and_recv(IpcRequest::Switch(None)).await?,
-                    IpcResponse::Empty
-                ) {
-                    eprintln!("unexpected response from server");
+        };
+        if let Some(status) = &status {
+            update(&status.advanced(received.elapsed()), changed)?;
+        }
+    }
+}
+
+/// Read the clicks that i3blocks writes to stdin for persistent blocks, returns their buttons
+fn read_clicks() -> mpsc::Receiver<u64> {
+    #[derive(serde::Deserialize)]
+    struct Click {
+        button: u64,
+    }
+    let (tx, rx) = mpsc::channel(4);
+    std::thread::spawn(move || {
+        for line in io::stdin().lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            // a JSON object, or only the button with older versions of i3blocks
+            let button = serde_json::from_str::<Click>(&line)
+                .map(|click| click.button)
+                .or_else(|_| line.trim().parse());
+            match button {
+                Ok(button) => {
+                    if tx.blocking_send(button).is_err() {
+                        break;
+                    }
                 }
+                Err(_) => log::debug!("ignoring input '{line}'"),
             }
-            Command::Stats => {
-                let events = ActivityRead::load()?.read()?;
-                let start = Zoned::now()
-                    .with()
-                    .hour(0)
-                    .minute(0)
-                    .second(0)
-                    .build()?
-                    .timestamp()
-                    .as_second();
-                let end = Zoned::now()
-                    .with()
-                    .hour(23)
-                    .minute(59)
-                    .second(59)
-                    .build()?
-                    .timestamp()
-                    .as_second();
-                let mut totals: BTreeMap<Activity, i64> = BTreeMap::new();
-
-                let mut prev: Option<Activity> = None;
-                let mut prev_time = None;
-
-                println!("Activities today:");
-                for event in events {
-                    if event.timestamp >= start && event.timestamp <= end {
-                        match event.event {
-                            Event::Power(state) => {
-                                if !state {
-                                    // activity ends on poweroff
-                                    prev = None;
-                                    prev_time = None;
-                                }
-                            }
-                            Event::SwitchActivity(activity) => {
-                                if let (Some(prev_activity), Some(prev_time)) = (prev, prev_time) {
-                                    let duration = event.timestamp - prev_time;
-                                    println!(
-                                        "{} - {}\t{}\t{:#}",
-                                        Timestamp::new(prev_time, 0)
-                                            .unwrap()
-                                            .to_zoned(TimeZone::system())
-                                            .time(),
-                                        Timestamp::new(event.timestamp, 0)
-                                            .unwrap()
-                                            .to_zoned(TimeZone::system())
-                                            .time(),
-                                        prev_activity,
-                                        SignedDuration::new(duration, 0)
-                                    );
-                                    *totals.entry(prev_activity.clone()).or_insert(0) += duration;
-                                }
-                                prev = activity;
-                                prev_time = Some(event.timestamp);
-                            }
-                        }
+        }
+    });
+    rx
+}
+
+/// Stop the current activity, or resume the last one when there is none
+async fn toggle(client: &mut Client) -> Result<()> {
+    if client.status().await?.activity().is_some() {
+        client.stop().await?;
+    } else {
+        let timeline = Timeline::load()?;
+        let last = timeline
+            .sessions()
+            .last()
+            .context("no previous activity to resume")?;
+        client.switch(last.activity.clone()).await?;
+    }
+    Ok(())
+}
+
+async fn run(mut client: Client, format: Format, cmd: Command) -> Result<()> {
+    match cmd {
+        Command::List => {
+            let activities = client.activities().await?;
+            match format {
+                Format::Text => {
+                    for info in &activities {
+                        println!("{}", describe_activity(info));
                     }
                 }
+                Format::Json => print_json(&activities)?,
+                Format::Csv => print_csv(
+                    &[
+                        "activity",
+                        "name",
+                        "aliases",
+                        "description",
+                        "color",
+                        "client",
+                        "project",
+                        "billable",
+                        "hourly_rate",
+                    ],
+                    activities.into_iter().map(|info| {
+                        vec![
+                            info.activity.to_string(),
+                            info.name.unwrap_or_default(),
+                            info.aliases.join(" "),
+                            info.description.unwrap_or_default(),
+                            info.color.unwrap_or_default(),
+                            info.client.unwrap_or_default(),
+                            info.project.unwrap_or_default(),
+                            info.billable.to_string(),
+                            info.hourly_rate.map(|r| r.to_string()).unwrap_or_default(),
+                        ]
+                    }),
+                ),
+            }
+        }
+        Command::Switch { activity } => {
+            let activity = resolve_activity(&mut client, &activity).await?;
+            client.switch(activity).await?;
+        }
+        Command::Status => {
+            let status = client.status().await?;
+            print_status(&status, format, DEFAULT_TEMPLATE, true)?;
+        }
+        Command::Watch { template, interval } => {
+            let mut first = true;
+            watch_status(
+                &mut client,
+                Duration::from_secs(interval.max(1)),
+                None,
+                |status, _| {
+                    print_status(status, format, &template, first)?;
+                    first = false;
+                    Ok(())
+                },
+            )
+            .await?;
+        }
+        Command::Stop => {
+            client.stop().await?;
+        }
+        Command::Add { activity } => {
+            client.add_activity(Activity::new(activity)?).await?;
+        }
+        Command::Remove { activity } => {
+            client.remove_activity(Activity::new(activity)?).await?;
+        }
+        Command::Archive { activity } => {
+            client.archive_activity(Activity::new(activity)?).await?;
+        }
+        Command::Reload => {
+            client.reload_config().await?;
+        }
+        Command::Toggle => {
+            toggle(&mut client).await?;
+        }
+        Command::Bar {
+            kind,
+            watch,
+            goal,
+            template,
+            stopped,
+        } => {
+            let mut bar = Bar::new(kind, template, stopped, goal);
+            if watch {
+                let clicks = (kind == BarKind::I3blocks).then(read_clicks);
+                watch_status(
+                    &mut client,
+                    Duration::from_secs(1),
+                    clicks,
+                    |status, changed| {
+                        if changed {
+                            bar.refresh_today()?;
+                        }
+                        println!("{}", bar.render(status, true)?);
+                        Ok(())
+                    },
+                )
+                .await?;
+            } else {
+                // i3blocks passes clicks in the environment
+                if kind == BarKind::I3blocks
+                    && std::env::var("BLOCK_BUTTON").is_ok_and(|b| b == "1")
+                {
+                    toggle(&mut client).await?;
+                }
+                let status = client.status().await?;
+                bar.refresh_today()?;
+                println!("{}", bar.render(&status, false)?);
+            }
+        }
+        Command::Stats { .. } | Command::Log { .. } | Command::Export { .. } => {
+            unreachable!("offline commands do not use the daemon")
+        }
+    };
+    Ok(())
+}
+
+fn stats(
+    filter: Option<&ActivityFilter>,
+    period: &Period,
+    depth: Option<usize>,
+    format: Format,
+) -> Result<()> {
+    let tz = TimeZone::system();
+    let today = Zoned::now().date();
+    let (first, last, start, end) = period.window(today, &tz)?;
+    let timeline = load_timeline(filter, start, end)?;
+    let metadata = Metadata::load();
+    match format {
+        Format::Text => print_stats(&timeline, &metadata, depth, first, last, today, &tz),
+        Format::Json => print_json(&StatsOutput::new(
+            &timeline, &metadata, depth, first, last, &tz,
+        )?),
+        Format::Csv => {
+            print_sessions_csv(
+                StatsOutput::new(&timeline, &metadata, depth, first, last, &tz)?.sessions,
+            );
+            Ok(())
+        }
+    }
+}
+
+/// The sessions within `[start, end)` of the activities selected by the filter
+fn load_timeline(filter: Option<&ActivityFilter>, start: i64, end: i64) -> Result<Timeline> {
+    let timeline = Timeline::load()?.range(start, end);
+    Ok(match filter {
+        Some(filter) => timeline.filter(filter),
+        None => timeline,
+    })
+}
+
+fn show_log(period: &Period, format: Format) -> Result<()> {
+    let tz = TimeZone::system();
+    let (_, _, start, end) = period.window(Zoned::now().date(), &tz)?;
+    let events: Vec<EventOutput> = ActivityRead::load()?
+        .read()?
+        .into_iter()
+        .filter(|e| e.timestamp >= start && e.timestamp < end)
+        .map(|e| {
+            Ok(EventOutput {
+                time: format_timestamp(e.timestamp, &tz)?,
+                event: e.event.to_string(),
+            })
+        })
+        .collect::<Result<_>>()?;
+    match format {
+        Format::Text => {
+            for event in events {
+                println!("{}\t{}", event.time, event.event);
+            }
+            Ok(())
+        }
+        Format::Json => print_json(&events),
+        Format::Csv => {
+            print_csv(
+                &["time", "event"],
+                events.into_iter().map(|e| vec![e.time, e.event]),
+            );
+            Ok(())
+        }
+    }
+}
+
+fn export(filter: Option<&ActivityFilter>, period: &Period, format: Format) -> Result<()> {
+    let tz = TimeZone::system();
+    let (first, last, start, end) = period.window(Zoned::now().date(), &tz)?;
+    let timeline = load_timeline(filter, start, end)?;
+    let sessions = StatsOutput::new(&timeline, &Metadata::load(), None, first, last, &tz)?.sessions;
+    match format {
+        Format::Json => print_json(&sessions),
+        Format::Text | Format::Csv => {
+            print_sessions_csv(sessions);
+            Ok(())
+        }
+    }
+}
+
+fn print_sessions_csv(sessions: Vec<SessionOutput>) {
+    print_csv(
+        &[
+            "activity", "name", "client", "project", "billable", "start", "end", "duration",
+        ],
+        sessions.into_iter().map(|s| {
+            vec![
+                s.activity,
+                s.name,
+                s.client.unwrap_or_default(),
+                s.project.unwrap_or_default(),
+                s.billable.to_string(),
+                s.start,
+                s.end,
+                s.duration.to_string(),
+            ]
+        }),
+    )
+}
+
+/// Metadata of the configured and archived activities, read from the config file of the daemon
+struct Metadata(BTreeMap<Activity, ActivityInfo>);
+
+impl Metadata {
+    /// Falls back to no metadata, the time log can be read without a config
+    fn load() -> Self {
+        #[derive(Default, serde::Deserialize)]
+        struct ConfigActivities {
+            #[serde(default)]
+            activities: Vec<ActivityInfo>,
+            #[serde(default)]
+            archived: Vec<ActivityInfo>,
+        }
+        let config = config_path().and_then(|path| {
+            if !path.exists() {
+                return Ok(ConfigActivities::default());
+            }
+            let config_string = fs::read_to_string(path).context("failed to read config file")?;
+            toml::from_str(&config_string).context("failed to parse config file")
+        });
+        let config = config.unwrap_or_else(|e| {
+            log::warn!("not using activity metadata: {e:#}");
+            ConfigActivities::default()
+        });
+        Self(
+            config
+                .activities
+                .into_iter()
+                .chain(config.archived)
+                .map(|info| (info.activity.clone(), info))
+                .collect(),
+        )
+    }
+
+    fn get(&self, activity: &Activity) -> ActivityInfo {
+        self.0
+            .get(activity)
+            .cloned()
+            .unwrap_or_else(|| ActivityInfo::new(activity.clone()))
+    }
+}
+
+/// Find the activity the user meant, asking which one when several match
+async fn resolve_activity(client: &mut Client, query: &str) -> Result<Activity> {
+    let activities = client.activities().await?;
+    let activity = match find_activity(query, &activities) {
+        Match::Found(activity) => activity,
+        Match::Ambiguous(candidates) => choose_activity(query, &candidates)?,
+        Match::NotFound => bail!("no activity matches '{query}'"),
+    };
+    if activity.as_str() != query {
+        log::info!("'{query}' matches {activity}");
+    }
+    Ok(activity)
+}
+
+fn choose_activity(query: &str, candidates: &[Activity]) -> Result<Activity> {
+    let list = candidates
+        .iter()
+        .map(Activity::to_string)
+        .collect::<Vec<_>>()
+        .join(", ");
+    if !io::stdin().is_terminal() {
+        bail!("'{query}' matches several activities: {list}");
+    }
+    eprintln!("'{query}' matches several activities:");
+    for (i, activity) in candidates.iter().enumerate() {
+        eprintln!("  {}) {activity}", i + 1);
+    }
+    eprint!("Switch to [1-{}, empty to cancel]: ", candidates.len());
+    io::stderr().flush().context("failed to write prompt")?;
+    let mut answer = String::new();
+    io::stdin()
+        .read_line(&mut answer)
+        .context("failed to read answer")?;
+    let answer = answer.trim();
+    if answer.is_empty() {
+        bail!("no activity chosen");
+    }
+    answer
+        .parse::<usize>()
+        .ok()
+        .and_then(|i| candidates.get(i.checked_sub(1)?))
+        .cloned()
+        .with_context(|| format!("'{answer}' is not one of the choices"))
+}
 
-                println!("\nActivity totals for today:");
-                for (activity, duration) in totals {
-                    println!("{}\t{:#}", activity, SignedDuration::new(duration, 0));
+/// The key followed by the metadata that is set
+fn describe_activity(info: &ActivityInfo) -> String {
+    let mut details = Vec::new();
+    details.extend(info.name.clone());
+    if !info.aliases.is_empty() {
+        details.push(format!("aliases: {}", info.aliases.join(" ")));
+    }
+    match (&info.client, &info.project) {
+        (Some(client), Some(project)) => details.push(format!("{client} / {project}")),
+        (Some(name), None) | (None, Some(name)) => details.push(name.clone()),
+        (None, None) => {}
+    }
+    details.extend(info.description.clone());
+    if info.billable {
+        details.push(match info.hourly_rate {
+            Some(rate) => format!("billable at {rate}/h"),
+            None => "billable".to_string(),
+        });
+    }
+    if details.is_empty() {
+        info.activity.to_string()
+    } else {
+        format!("{}\t{}", info.activity, details.join(", "))
+    }
+}
+
+const DEFAULT_TEMPLATE: &str = "{status}";
+
+fn print_status(status: &Status, format: Format, template: &str, csv_header: bool) -> Result<()> {
+    let output = StatusOutput {
+        activity: status.activity().map(Activity::to_string),
+        duration: status.duration().as_secs(),
+        idle: status.is_idle(),
+        presence: status.presence() == Presence::Connected,
+    };
+    match format {
+        Format::Text => println!("{}", render_template(template, status)),
+        Format::Json => print_json(&output)?,
+        Format::Csv => {
+            let header: &[&str] = if csv_header {
+                &["activity", "duration", "idle", "presence"]
+            } else {
+                &[]
+            };
+            print_csv(
+                header,
+                [vec![
+                    output.activity.unwrap_or_default(),
+                    output.duration.to_string(),
+                    output.idle.to_string(),
+                    output.presence.to_string(),
+                ]],
+            )
+        }
+    }
+    Ok(())
+}
+
+fn render_template(template: &str, status: &Status) -> String {
+    template
+        .replace("{status}", &status.to_string())
+        .replace(
+            "{activity}",
+            &status
+                .activity()
+                .map(Activity::to_string)
+                .unwrap_or_default(),
+        )
+        .replace(
+            "{duration}",
+            &DisplayDuration(status.duration()).to_string(),
+        )
+        .replace("{idle}", if status.is_idle() { "idle" } else { "" })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum BarKind {
+    /// JSON for a waybar custom module, set 'on-click' to 'ttctl toggle'
+    Waybar,
+    /// Lines for an i3blocks block
+    I3blocks,
+    /// A line for a polybar script module, with a click action
+    Polybar,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct WaybarOutput {
+    text: String,
+    tooltip: String,
+    class: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    percentage: Option<u64>,
+}
+
+struct Bar {
+    kind: BarKind,
+    template: String,
+    stopped: String,
+    goal: Option<Duration>,
+    /// Time tracked today at `today_at`
+    today: Duration,
+    today_at: Instant,
+}
+
+impl Bar {
+    fn new(kind: BarKind, template: String, stopped: String, goal: Option<u64>) -> Self {
+        Self {
+            kind,
+            template,
+            stopped,
+            goal: goal.map(|minutes| Duration::from_secs(minutes * 60)),
+            today: Duration::ZERO,
+            today_at: Instant::now(),
+        }
+    }
+
+    /// Read the time tracked today from the time log
+    fn refresh_today(&mut self) -> Result<()> {
+        let today = Zoned::now().start_of_day()?;
+        let start = today.timestamp().as_second();
+        let end = today.tomorrow()?.timestamp().as_second();
+        let total = Timeline::load()?.range(start, end).total();
+        self.today = Duration::from_secs(total.max(0) as u64);
+        self.today_at = Instant::now();
+        Ok(())
+    }
+
+    fn render(&self, status: &Status, continuous: bool) -> Result<String> {
+        let active = status.activity().is_some() && !status.is_idle();
+        let today = if active {
+            self.today + self.today_at.elapsed()
+        } else {
+            self.today
+        };
+        let percentage = self
+            .goal
+            .map(|goal| (today.as_secs() * 100 / goal.as_secs().max(1)).min(100));
+        let text = if status.activity().is_some() {
+            render_template(
+                &self
+                    .template
+                    .replace("{percentage}", &percentage.unwrap_or_default().to_string()),
+                status,
+            )
+        } else {
+            self.stopped.clone()
+        };
+        let class = if status.activity().is_none() {
+            "stopped"
+        } else if status.is_idle() {
+            "idle"
+        } else {
+            "active"
+        };
+        Ok(match self.kind {
+            BarKind::Waybar => {
+                let mut tooltip = format!("{status}\nToday: {}", DisplayDuration(today));
+                if let (Some(goal), Some(percentage)) = (self.goal, percentage) {
+                    tooltip += &format!(" of {} ({percentage}%)", DisplayDuration(goal));
                 }
+                serde_json::to_string(&WaybarOutput {
+                    text,
+                    tooltip,
+                    class,
+                    percentage,
+                })
+                .context("failed to serialize output")?
+            }
+            // a persistent block reads one line per update, otherwise full and short text
+            BarKind::I3blocks if continuous => text,
+            BarKind::I3blocks => {
+                let short = status
+                    .activity()
+                    .map(Activity::to_string)
+                    .unwrap_or_default();
+                format!("{text}\n{short}")
+            }
+            BarKind::Polybar => format!("%{{A1:ttctl toggle:}}{text}%{{A}}"),
+        })
+    }
+}
+
+fn print_stats(
+    timeline: &Timeline,
+    metadata: &Metadata,
+    depth: Option<usize>,
+    first: Date,
+    last: Date,
+    today: Date,
+    tz: &TimeZone,
+) -> Result<()> {
+    let description = if first == last {
+        if first == today {
+            "today".to_string()
+        } else {
+            format!("on {first}")
+        }
+    } else {
+        format!("from {first} to {last}")
+    };
+
+    if first == last {
+        println!("Activities {description}:");
+        for session in timeline {
+            println!(
+                "{} - {}\t{}\t{:#}",
+                format_time(session.start),
+                format_time(session.end),
+                metadata.get(&session.activity).display_name(),
+                SignedDuration::from_secs(session.duration())
+            );
+        }
+    } else {
+        println!("Activities per day {description}:");
+        for (date, day) in timeline.by_day(tz)? {
+            println!(
+                "{}\t{:#}",
+                date.strftime("%a %Y-%m-%d"),
+                SignedDuration::from_secs(day.total())
+            );
+            for total in rollup(&day, metadata, depth) {
+                println!("  {}", total.describe(metadata));
             }
+        }
+    }
+
+    println!("\nActivity totals {description}:");
+    for total in rollup(timeline, metadata, depth) {
+        println!("{}", total.describe(metadata));
+    }
+    let billed = timeline
+        .by_activity()
+        .into_iter()
+        .filter_map(|(activity, duration)| metadata.get(&activity).amount(duration))
+        .reduce(|a, b| a + b);
+    if let Some(billed) = billed {
+        println!("\nBillable total {description}: {billed:.2}");
+    }
+    Ok(())
+}
+
+/// The time spent on an activity including its children, see [`Timeline::rollup`]
+struct Total {
+    activity: Activity,
+    duration: i64,
+    /// Billed amount of the activity and its children, if any of them is billable with a rate
+    amount: Option<f64>,
+}
+
+impl Total {
+    /// A line of the tree of totals, children are indented below their parent
+    fn describe(&self, metadata: &Metadata) -> String {
+        let info = metadata.get(&self.activity);
+        let indent = "  ".repeat(self.activity.depth() - 1);
+        let label = info.name.as_deref().unwrap_or(self.activity.leaf());
+        let duration = SignedDuration::from_secs(self.duration);
+        match self.amount {
+            Some(amount) => format!("{indent}{label}\t{duration:#}\t{amount:.2}"),
+            None => format!("{indent}{label}\t{duration:#}"),
+        }
+    }
+}
+
+/// Totals of every activity and its parents, parents before their children
+fn rollup(timeline: &Timeline, metadata: &Metadata, depth: Option<usize>) -> Vec<Total> {
+    // billing depends on the activity that was tracked, so amounts are rolled up separately
+    let mut amounts: BTreeMap<Activity, f64> = BTreeMap::new();
+    for (activity, duration) in timeline.by_activity() {
+        if let Some(amount) = metadata.get(&activity).amount(duration) {
+            for ancestor in activity.ancestors() {
+                *amounts.entry(ancestor).or_default() += amount;
+            }
+        }
+    }
+    timeline
+        .rollup()
+        .into_iter()
+        .filter(|(activity, _)| depth.is_none_or(|depth| activity.depth() <= depth))
+        .map(|(activity, duration)| Total {
+            amount: amounts.get(&activity).copied(),
+            activity,
+            duration,
+        })
+        .collect()
+}
+
+fn format_time(timestamp: i64) -> jiff::civil::Time {
+    Timestamp::new(timestamp, 0)
+        .unwrap()
+        .to_zoned(TimeZone::system())
+        .time()
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    /// Human readable text
+    #[default]
+    Text,
+    /// JSON with stable field names, durations are in seconds
+    Json,
+    /// Comma-separated values with a header row
+    Csv,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct StatusOutput {
+    activity: Option<String>,
+    duration: u64,
+    idle: bool,
+    /// Whether the daemon receives presence data from actived
+    presence: bool,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct StatsOutput {
+    from: Date,
+    to: Date,
+    sessions: Vec<SessionOutput>,
+    days: Vec<DayOutput>,
+    totals: Vec<TotalOutput>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct SessionOutput {
+    activity: String,
+    name: String,
+    client: Option<String>,
+    project: Option<String>,
+    billable: bool,
+    start: String,
+    end: String,
+    duration: i64,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct DayOutput {
+    date: Date,
+    total: i64,
+    activities: Vec<TotalOutput>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct TotalOutput {
+    activity: String,
+    name: String,
+    /// Included in the total of the parent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parent: Option<String>,
+    /// Including the time of all children
+    duration: i64,
+    /// Billed amount, for billable activities with a rate
+    #[serde(skip_serializing_if = "Option::is_none")]
+    amount: Option<f64>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct EventOutput {
+    time: String,
+    event: String,
+}
+
+fn format_timestamp(timestamp: i64, tz: &TimeZone) -> Result<String> {
+    Ok(Timestamp::from_second(timestamp)?
+        .to_zoned(tz.clone())
+        .strftime("%Y-%m-%dT%H:%M:%S%:z")
+        .to_string())
+}
+
+impl StatsOutput {
+    fn new(
+        timeline: &Timeline,
+        metadata: &Metadata,
+        depth: Option<usize>,
+        from: Date,
+        to: Date,
+        tz: &TimeZone,
+    ) -> Result<Self> {
+        let totals = |timeline: &Timeline| {
+            rollup(timeline, metadata, depth)
+                .into_iter()
+                .map(|total| TotalOutput {
+                    activity: total.activity.to_string(),
+                    name: metadata.get(&total.activity).display_name().to_string(),
+                    parent: total.activity.parent().as_ref().map(Activity::to_string),
+                    duration: total.duration,
+                    amount: total.amount,
+                })
+                .collect()
         };
-        Ok(())
+        Ok(Self {
+            from,
+            to,
+            sessions: timeline
+                .iter()
+                .map(|session| {
+                    let info = metadata.get(&session.activity);
+                    Ok(SessionOutput {
+                        activity: session.activity.to_string(),
+                        name: info.display_name().to_string(),
+                        client: info.client,
+                        project: info.project,
+                        billable: info.billable,
+                        start: format_timestamp(session.start, tz)?,
+                        end: format_timestamp(session.end, tz)?,
+                        duration: session.duration(),
+                    })
+                })
+                .collect::<Result<_>>()?,
+            days: timeline
+                .by_day(tz)?
+                .into_iter()
+                .map(|(date, day)| DayOutput {
+                    date,
+                    total: day.total(),
+                    activities: totals(&day),
+                })
+                .collect(),
+            totals: totals(timeline),
+        })
+    }
+}
+
+fn print_json<T: serde::Serialize>(value: &T) -> Result<()> {
+    println!(
+        "{}",
+        serde_json::to_string(value).context("failed to serialize output")?
+    );
+    Ok(())
+}
+
+fn print_csv<R>(header: &[&str], rows: impl IntoIterator<Item = R>)
+where
+    R: IntoIterator<Item = String>,
+{
+    fn escape(field: &str) -> String {
+        if field.contains([',', '"', '\n']) {
+            format!("\"{}\"", field.replace('"', "\"\""))
+        } else {
+            field.to_string()
+        }
+    }
+    if !header.is_empty() {
+        println!("{}", header.join(","));
+    }
+    for row in rows {
+        let fields: Vec<String> = row.into_iter().map(|f| escape(&f)).collect();
+        println!("{}", fields.join(","));
     }
 }
@@ -1,16 +1,22 @@
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
+use inotify::{Inotify, WatchMask};
 use std::sync::Mutex;
 use std::{
+    collections::BTreeSet,
     fs::{self},
     sync::Arc,
-    time::SystemTime,
+    time::{Duration, SystemTime},
 };
 use tokio::signal::unix::{SignalKind, signal};
-use ttd::async_socket::SocketStream;
+use tokio::sync::{broadcast, mpsc};
+use tokio_stream::StreamExt;
+use toml_edit::{Array, ArrayOfTables, DocumentMut, InlineTable, Item, Table, Value};
+use ttd::async_socket::{CAP_PERSISTENT, ConnectionClosed, SocketStream};
 use ttd::{
-    APP_NAME, Activity, Event, IpcRequest, Status, async_socket::SocketServer, get_unix_time,
+    ACTIVITY_DAEMON_NAME, ActivityInfo, ActivityLog, ActivityMessage, ActivityRead, CAP_SUBSCRIBE,
+    ErrorKind, IpcResponse, Notification, Presence, config_path, timeline::last_activity,
 };
-use ttd::{ActivityLog, ActivityMessage, IpcResponse};
+use ttd::{Activity, Event, IpcRequest, Status, async_socket::SocketServer, get_unix_time};
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<()> {
@@ -18,41 +24,414 @@ async fn main() -> Result<()> {
         .filter_level(log::LevelFilter::Info)
         .parse_default_env()
         .init();
-    let config = Config::load().expect("failed to load config");
+    let config = Config::load().context("failed to load config")?;
+    // loading the log first closes the previous run if it stopped uncleanly
     let activity_log = ActivityLog::load().expect("failed to load activity log");
+    let last_activity = ActivityRead::load()
+        .and_then(|mut log| log.read())
+        .map(|events| last_activity(&events))
+        .unwrap_or_else(|e| {
+            log::warn!("failed to read previous activity: {e:#}");
+            None
+        });
 
-    Daemon::new(config, activity_log).run().await?;
+    let mut daemon = Daemon::new(config, activity_log);
+    daemon.resume(last_activity)?;
+    daemon.run().await?;
     Ok(())
 }
 
-#[derive(Default, Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 struct Config {
-    activities: Vec<Activity>,
+    #[serde(default)]
+    activities: Vec<ActivityInfo>,
+    /// Activities that can no longer be switched to, kept such that they can be restored
+    #[serde(default)]
+    archived: Vec<ActivityInfo>,
+    /// Minutes without input after which the time is no longer counted for the current activity
+    #[serde(default)]
+    idle_timeout: Option<u64>,
+    /// Keep the activity running when input returns after being idle, otherwise stop it
+    #[serde(default = "default_true")]
+    resume_after_idle: bool,
+    /// Whether to resume the last activity when the daemon starts
+    #[serde(default)]
+    resume: ResumePolicy,
+}
+
+/// In the config file: `resume = "never"`, `resume = "always"` or `resume = { within = 30 }`
+#[derive(Default, Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ResumePolicy {
+    /// Always start without an activity
+    #[default]
+    Never,
+    /// Resume the last activity
+    Always,
+    /// Resume the last activity if the daemon was stopped for less than the given minutes
+    Within(u64),
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            activities: Vec::new(),
+            archived: Vec::new(),
+            idle_timeout: None,
+            resume_after_idle: true,
+            resume: ResumePolicy::default(),
+        }
+    }
 }
 
 impl Config {
     fn load() -> Result<Self> {
-        let dir = dirs::config_dir().context("no config dir")?.join(APP_NAME);
-        if !dir.exists() {
-            fs::create_dir_all(&dir).context("failed to create config dir")?;
-        }
-        let path = dir.join("config.toml");
+        let path = config_path()?;
         if path.exists() {
             let config_string =
                 std::fs::read_to_string(path).context("failed to read config file")?;
-            toml::from_str(&config_string).context("failed to parse config file")
+            let config: Config =
+                toml::from_str(&config_string).context("failed to parse config file")?;
+            config.validate()?;
+            Ok(config)
         } else {
             log::warn!("no config file found, using defaults");
             Ok(Config::default())
         }
     }
+
+    fn validate(&self) -> Result<()> {
+        let mut seen = BTreeSet::new();
+        for info in self.activities.iter().chain(&self.archived) {
+            let activity = &info.activity;
+            Activity::new_legacy(activity.to_string())?;
+            if activity.is_legacy() {
+                log::warn!("activity '{activity}' has an empty segment, it has no parents");
+            }
+            if !seen.insert(activity) {
+                bail!("activity '{activity}' is listed more than once");
+            }
+        }
+        let mut aliases = BTreeSet::new();
+        for info in self.activities.iter().chain(&self.archived) {
+            let activity = &info.activity;
+            for alias in &info.aliases {
+                if alias.is_empty() {
+                    bail!("activity '{activity}' has an empty alias");
+                }
+                // parents can be switched to as well
+                if seen
+                    .iter()
+                    .flat_map(|a| a.ancestors())
+                    .any(|a| a.as_str() == alias)
+                {
+                    bail!("alias '{alias}' of '{activity}' is also an activity");
+                }
+                if !aliases.insert(alias) {
+                    bail!("alias '{alias}' is used more than once");
+                }
+            }
+            if info.hourly_rate.is_some_and(|rate| rate < 0.0) {
+                bail!("activity '{activity}' has a negative hourly_rate");
+            }
+        }
+        if self.idle_timeout == Some(0) {
+            bail!("idle_timeout must be at least one minute");
+        }
+        Ok(())
+    }
+
+    fn is_configured(&self, activity: &Activity) -> bool {
+        self.activities.iter().any(|a| &a.activity == activity)
+    }
+
+    /// Configured activities and their parents, which track time not meant for a specific child
+    fn can_track(&self, activity: &Activity) -> bool {
+        self.activities
+            .iter()
+            .any(|a| a.activity.is_within(activity))
+    }
+
+    /// Write the activities back to the config file, keeping its formatting and comments
+    fn save_activities(&self) -> Result<()> {
+        let path = config_path()?;
+        let mut doc = if path.exists() {
+            fs::read_to_string(&path)
+                .context("failed to read config file")?
+                .parse::<DocumentMut>()
+                .context("failed to parse config file")?
+        } else {
+            DocumentMut::new()
+        };
+        self.update_activities(&mut doc);
+        // write through a symlink, e.g. to a config managed with the other dotfiles
+        let path = if path.exists() {
+            fs::canonicalize(&path).context("failed to resolve config file")?
+        } else {
+            path
+        };
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, doc.to_string()).context("failed to write config file")?;
+        fs::rename(&tmp_path, &path).context("failed to write config file")?;
+        Ok(())
+    }
+
+    fn update_activities(&self, doc: &mut DocumentMut) {
+        // entries keep their comments and formatting when moved between the lists
+        let existing: Vec<Entry> = ["activities", "archived"]
+            .iter()
+            .filter_map(|key| doc.get(key))
+            .flat_map(|item| match item {
+                Item::ArrayOfTables(tables) => tables.iter().cloned().map(Entry::Table).collect(),
+                item => item.as_array().map(inline_entries).unwrap_or_default(),
+            })
+            .collect();
+        // a list that is not in the file yet uses the layout of the other one
+        let tables = ["activities", "archived"]
+            .iter()
+            .any(|key| doc.get(key).is_some_and(Item::is_array_of_tables));
+        for (key, activities) in [
+            ("activities", &self.activities),
+            ("archived", &self.archived),
+        ] {
+            if key == "archived" && activities.is_empty() {
+                doc.remove(key);
+            } else if !activities.is_empty()
+                && doc.get(key).map_or(tables, Item::is_array_of_tables)
+            {
+                set_activity_tables(doc, key, activities, &existing);
+            } else {
+                // an empty array of tables would not be written at all
+                set_activities(doc, key, activities, &existing);
+            }
+        }
+    }
+}
+
+/// An activity in the config file, either in an inline array or an array of tables
+enum Entry {
+    /// The comment after the entry on the same line is in the prefix of the next entry, so it
+    /// is kept separately to move it along with the entry
+    Inline {
+        value: Value,
+        comment: Option<String>,
+    },
+    Table(Table),
+}
+
+impl Entry {
+    fn key(&self) -> Option<&str> {
+        match self {
+            Self::Inline { value, .. } => value.as_inline_table()?.get("key")?.as_str(),
+            Self::Table(table) => table.get("key")?.as_str(),
+        }
+    }
+
+    fn find<'a>(entries: &'a [Entry], activity: &Activity) -> Option<&'a Entry> {
+        entries
+            .iter()
+            .find(|entry| entry.key() == Some(activity.as_str()))
+    }
+}
+
+fn prefix(value: &Value) -> &str {
+    value
+        .decor()
+        .prefix()
+        .and_then(|p| p.as_str())
+        .unwrap_or_default()
+}
+
+/// Split a comment at the start of the whitespace before an entry, which belongs to the line
+/// of the previous entry or of the opening bracket
+fn split_line_comment(whitespace: &str) -> (Option<&str>, &str) {
+    match whitespace.find('\n') {
+        Some(i) if whitespace[..i].trim_start().starts_with('#') => {
+            (Some(&whitespace[..i]), &whitespace[i..])
+        }
+        _ => (None, whitespace),
+    }
+}
+
+/// The entries of an inline array, without the comments of the lines they start on
+fn inline_entries(array: &Array) -> Vec<Entry> {
+    let values: Vec<&Value> = array.iter().collect();
+    let trailing = array.trailing().as_str().unwrap_or_default();
+    values
+        .iter()
+        .enumerate()
+        .map(|(i, &value)| {
+            let mut value = value.clone();
+            if let (Some(_), rest) = split_line_comment(prefix(&value)) {
+                let rest = rest.to_string();
+                value.decor_mut().set_prefix(rest);
+            }
+            let next = values.get(i + 1).map_or(trailing, |next| prefix(next));
+            let (comment, _) = split_line_comment(next);
+            Entry::Inline {
+                value,
+                comment: comment.map(str::to_string),
+            }
+        })
+        .collect()
 }
+
+/// Put a line comment in front of whitespace, which has to continue on a new line after it
+fn with_line_comment(comment: Option<&str>, whitespace: &str) -> String {
+    match comment {
+        Some(comment) if whitespace.starts_with('\n') => format!("{comment}{whitespace}"),
+        Some(comment) => format!("{comment}\n{}", whitespace.trim_start()),
+        None => whitespace.to_string(),
+    }
+}
+
+/// Replace an array of activities, reusing the existing entries such that they keep their comments
+fn set_activities(
+    doc: &mut DocumentMut,
+    key: &str,
+    activities: &[ActivityInfo],
+    existing: &[Entry],
+) {
+    let mut array = doc
+        .get(key)
+        .and_then(Item::as_array)
+        .cloned()
+        .unwrap_or_default();
+    let last = array.iter().last().cloned();
+    // the comment after the opening bracket stays there, the one after the last entry moves
+    let header = array
+        .get(0)
+        .and_then(|first| split_line_comment(prefix(first)).0)
+        .map(str::to_string);
+    let trailing = split_line_comment(array.trailing().as_str().unwrap_or_default())
+        .1
+        .to_string();
+    array.clear();
+    let mut comment = header;
+    for info in activities {
+        let (mut value, next_comment) = match Entry::find(existing, &info.activity) {
+            Some(Entry::Inline { value, comment }) => (value.clone(), comment.clone()),
+            Some(Entry::Table(table)) => {
+                (Value::InlineTable(table.clone().into_inline_table()), None)
+            }
+            None => {
+                let mut value = Value::InlineTable(activity_table(info));
+                // indent new entries like the last one, without copying its comments
+                let indent = last.as_ref().map(prefix).map(|p| match p.rfind('\n') {
+                    Some(i) => format!("\n{}", &p[i + 1..]),
+                    None => p.to_string(),
+                });
+                if let Some(indent) = indent {
+                    value.decor_mut().set_prefix(indent);
+                }
+                (value, None)
+            }
+        };
+        if comment.is_some() {
+            let prefix = with_line_comment(comment.as_deref(), prefix(&value));
+            value.decor_mut().set_prefix(prefix);
+        }
+        comment = next_comment;
+        array.push_formatted(value);
+    }
+    array.set_trailing(with_line_comment(comment.as_deref(), &trailing));
+    // entries on a single line may have been first or later in their previous array
+    for (i, value) in array.iter_mut().enumerate() {
+        if !prefix(value).contains('\n') {
+            value.decor_mut().set_prefix(if i == 0 { "" } else { " " });
+        }
+    }
+    // entries reused from the other array keep their layout, close a new array to match it
+    if last.is_none()
+        && array
+            .get(0)
+            .is_some_and(|first| prefix(first).contains('\n'))
+    {
+        array.set_trailing_comma(true);
+        if !array.trailing().as_str().is_some_and(|t| t.contains('\n')) {
+            let trailing = format!("{}\n", array.trailing().as_str().unwrap_or_default());
+            array.set_trailing(trailing);
+        }
+    }
+    // replacing an array of tables would keep the formatting of its key
+    if doc.get(key).is_some_and(Item::is_array_of_tables) {
+        doc.remove(key);
+    }
+    doc[key] = Item::Value(Value::Array(array));
+}
+
+/// Like [`set_activities`], for a config that lists the activities as `[[activities]]` tables
+fn set_activity_tables(
+    doc: &mut DocumentMut,
+    key: &str,
+    activities: &[ActivityInfo],
+    existing: &[Entry],
+) {
+    // tables are written in the order of their position, keep them where the list was
+    let position = doc
+        .get(key)
+        .and_then(Item::as_array_of_tables)
+        .and_then(|tables| tables.iter().find_map(Table::position));
+    let mut tables = ArrayOfTables::new();
+    for info in activities {
+        let mut table = match Entry::find(existing, &info.activity) {
+            Some(Entry::Table(table)) => table.clone(),
+            Some(Entry::Inline { value, .. }) => value
+                .as_inline_table()
+                .cloned()
+                .unwrap_or_else(|| activity_table(info))
+                .into_table(),
+            None => activity_table(info).into_table(),
+        };
+        if let Some(position) = position {
+            table.set_position(position);
+        }
+        tables.push(table);
+    }
+    doc[key] = Item::ArrayOfTables(tables);
+}
+
+fn activity_table(info: &ActivityInfo) -> InlineTable {
+    let mut table = InlineTable::new();
+    table.insert("key", info.activity.as_str().into());
+    let fields = [
+        ("name", &info.name),
+        ("description", &info.description),
+        ("color", &info.color),
+        ("client", &info.client),
+        ("project", &info.project),
+    ];
+    for (field, value) in fields {
+        if let Some(value) = value {
+            table.insert(field, value.as_str().into());
+        }
+    }
+    if !info.aliases.is_empty() {
+        table.insert("aliases", info.aliases.iter().collect::<Array>().into());
+    }
+    if info.billable {
+        table.insert("billable", true.into());
+    }
+    if let Some(rate) = info.hourly_rate {
+        table.insert("hourly_rate", rate.into());
+    }
+    table
+}
+
 struct Daemon {
     config: Config,
     activity_log: ActivityLog,
     started: SystemTime,
     current: Option<Activity>,
     last_active: u64,
+    idle_since: Option<u64>,
+    idle_time: Duration,
+    presence: Presence,
+    notify_tx: broadcast::Sender<IpcResponse>,
 }
 
 impl Daemon {
@@ -63,17 +442,31 @@ impl Daemon {
             started: SystemTime::now(),
             current: None,
             last_active: get_unix_time(),
+            idle_since: None,
+            idle_time: Duration::ZERO,
+            presence: Presence::Disconnected,
+            notify_tx: broadcast::channel(16).0,
         }
     }
 
     async fn run(self) -> Result<()> {
-        let mut listener = SocketServer::create(ttd::socket_path(), true)
+        let mut listener = SocketServer::create(ttd::socket_path(), 0o600)
             .await
             .context("failed to create socket server")?;
-        let mut activity_stream = SocketStream::connect(ttd::activity_daemon_socket()).await?;
+        let (presence_tx, mut presence_rx) = mpsc::channel(16);
+        tokio::spawn(run_presence(presence_tx));
+        let (config_tx, mut config_rx) = mpsc::channel(1);
+        tokio::spawn(async move {
+            if let Err(e) = watch_config(config_tx).await {
+                log::warn!("not watching the config file, reload with SIGHUP instead: {e:#}");
+            }
+        });
 
         let mut sigterm = signal(SignalKind::terminate())?;
         let mut sigint = signal(SignalKind::interrupt())?;
+        let mut sighup = signal(SignalKind::hangup())?;
+        let mut idle_check = tokio::time::interval(Duration::from_secs(10));
+        let mut checkpoint = tokio::time::interval(Duration::from_secs(60));
         let daemon = Arc::new(Mutex::new(self));
         loop {
             tokio::select! {
@@ -85,19 +478,52 @@ impl Daemon {
                     log::info!("received SIGINT, shutting down");
                     break;
                 }
+                _ = sighup.recv() => {
+                    log::info!("received SIGHUP, reloading config");
+                    // errors are reported to clients, the previous config remains in use
+                    let _ = daemon.lock().unwrap().reload_config();
+                }
+                Some(()) = config_rx.recv() => {
+                    log::info!("config file changed, reloading config");
+                    let _ = daemon.lock().unwrap().reload_config();
+                }
                 Ok(client_stream) = listener.accept_client() => {
                     tokio::spawn({
                         let daemon = daemon.clone();
                         async move {
-                            if let Err(e) = Self::handle_client(client_stream, daemon).await {
-                                log::error!("client handler error: {e:?}");
+                            match Self::handle_client(client_stream, daemon).await {
+                                Err(e) if e.is::<ConnectionClosed>() => {
+                                    log::debug!("client disconnected");
+                                }
+                                Err(e) => log::error!("client handler error: {e:?}"),
+                                Ok(()) => {}
                             }
                         }
                     });
                 }
-                Ok(activity) = activity_stream.recv::<ActivityMessage>() => {
+                Some(event) = presence_rx.recv() => {
+                    let mut daemon = daemon.lock().unwrap();
+                    match event {
+                        PresenceEvent::Connected => daemon.presence = Presence::Connected,
+                        PresenceEvent::Disconnected => daemon.presence = Presence::Disconnected,
+                        PresenceEvent::Active(last_active) => {
+                            if let Err(e) = daemon.set_last_active(last_active) {
+                                log::error!("{e:?}");
+                            }
+                        }
+                    }
+                }
+                _ = idle_check.tick() => {
                     let mut daemon = daemon.lock().unwrap();
-                    daemon.last_active = activity.last_active;
+                    if let Err(e) = daemon.check_idle() {
+                        log::error!("{e:?}");
+                    }
+                }
+                _ = checkpoint.tick() => {
+                    let daemon = daemon.lock().unwrap();
+                    if let Err(e) = daemon.activity_log.checkpoint() {
+                        log::error!("{e:?}");
+                    }
                 }
             }
         }
@@ -105,44 +531,630 @@ impl Daemon {
     }
 
     async fn handle_client(mut stream: SocketStream, daemon: Arc<Mutex<Daemon>>) -> Result<()> {
-        let msg: IpcRequest = stream.recv().await?;
-        let resp = {
-            let mut daemon = daemon.lock().unwrap();
-            daemon.handle_msg(msg)?
+        // the socket is private, but the runtime dir may be shared or have loose permissions
+        if !stream.peer().is_current_user() {
+            log::warn!("rejected connection from {}", stream.peer());
+            return Ok(());
+        }
+        stream
+            .accept_hello(&[CAP_SUBSCRIBE, CAP_PERSISTENT])
+            .await?;
+        if stream.has_capability(CAP_PERSISTENT) {
+            return Self::handle_persistent(stream, daemon).await;
+        }
+        let msg: IpcRequest = match stream.recv_timeout(stream.timeout()).await {
+            Ok(msg) => msg,
+            Err(e) => {
+                let _ = stream
+                    .send(IpcResponse::error(ErrorKind::InvalidRequest, e.to_string()))
+                    .await;
+                return Err(e);
+            }
         };
+        if let IpcRequest::Subscribe = msg {
+            return Self::handle_subscriber(stream, daemon).await;
+        }
+        let resp = daemon.lock().unwrap().respond(msg);
         stream.send(resp).await?;
         Ok(())
     }
 
+    /// Answer requests until the client disconnects, after subscribing notifications are pushed
+    async fn handle_persistent(stream: SocketStream, daemon: Arc<Mutex<Daemon>>) -> Result<()> {
+        let (notification_tx, notification_rx) = mpsc::channel(16);
+        let mut subscribed = false;
+        stream
+            .serve(notification_rx, |request| {
+                let mut daemon = daemon.lock().unwrap();
+                std::future::ready(match request {
+                    Ok(IpcRequest::Subscribe) => {
+                        if !subscribed {
+                            subscribed = true;
+                            tokio::spawn(forward_notifications(
+                                daemon.notify_tx.subscribe(),
+                                notification_tx.clone(),
+                            ));
+                        }
+                        IpcResponse::Status(daemon.status())
+                    }
+                    Ok(msg) => daemon.respond(msg),
+                    Err(e) => IpcResponse::error(ErrorKind::InvalidRequest, format!("{e:#}")),
+                })
+            })
+            .await
+    }
+
+    /// Send the current status, followed by a notification for every change
+    async fn handle_subscriber(mut stream: SocketStream, daemon: Arc<Mutex<Daemon>>) -> Result<()> {
+        let (status, mut notify_rx) = {
+            let daemon = daemon.lock().unwrap();
+            (daemon.status(), daemon.notify_tx.subscribe())
+        };
+        stream.send(IpcResponse::Status(status)).await?;
+        loop {
+            match notify_rx.recv().await {
+                Ok(notification) => stream.send(notification).await?,
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    log::warn!("subscriber missed {n} notifications");
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+        Ok(())
+    }
+
+    fn resume(&mut self, last: Option<(Activity, i64)>) -> Result<()> {
+        let Some((activity, stopped)) = last else {
+            return Ok(());
+        };
+        let gap = (get_unix_time() as i64 - stopped).max(0) as u64;
+        let resume = match self.config.resume {
+            ResumePolicy::Never => false,
+            ResumePolicy::Always => true,
+            ResumePolicy::Within(minutes) => gap < minutes * 60,
+        };
+        if !resume {
+            log::info!("not resuming {} after {}s", activity, gap);
+        } else if !self.config.can_track(&activity) {
+            log::warn!("not resuming unknown activity {}", activity);
+        } else {
+            log::info!("resuming {} after {}s", activity, gap);
+            self.switch(Some(activity))?;
+        }
+        Ok(())
+    }
+
+    fn set_last_active(&mut self, last_active: u64) -> Result<()> {
+        // actived repeats its last input on every connect, which is no new input and may
+        // predate the events logged since
+        self.last_active = self.last_active.max(last_active);
+        if let Some(idle_since) = self.idle_since
+            && last_active > idle_since
+        {
+            self.idle_since = None;
+            log::info!("input received, no longer idle");
+            let now = get_unix_time();
+            self.activity_log.log_at(now, Event::Idle(false))?;
+            self.idle_time +=
+                Duration::from_secs(now.saturating_sub(idle_since.max(self.started_secs())));
+            self.notify(Notification::IdleEnded);
+            if !self.config.resume_after_idle && self.current.is_some() {
+                log::info!("not resuming after idle, switching to no activity");
+                self.switch(None)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn check_idle(&mut self) -> Result<()> {
+        let Some(idle_timeout) = self.config.idle_timeout else {
+            return Ok(());
+        };
+        // without presence data the last input time is unknown
+        if self.presence != Presence::Connected {
+            return Ok(());
+        }
+        if self.idle_since.is_some()
+            || get_unix_time().saturating_sub(self.last_active) < idle_timeout * 60
+        {
+            return Ok(());
+        }
+        log::info!("no input for {} minutes, now idle", idle_timeout);
+        // the idle period started at the last input
+        self.activity_log
+            .log_at(self.last_active, Event::Idle(true))?;
+        self.idle_since = Some(self.last_active);
+        self.notify(Notification::IdleStarted);
+        Ok(())
+    }
+
+    /// Load the config file again, keeping the current config when the new one is not valid
+    fn reload_config(&mut self) -> Result<()> {
+        let config = Config::load().inspect_err(|e| {
+            log::error!("failed to reload config: {e:#}");
+            self.notify(Notification::ConfigError(format!("{e:#}")));
+        })?;
+        if config == self.config {
+            log::debug!("config did not change");
+            return Ok(());
+        }
+        self.config = config;
+        log::info!("reloaded config");
+        if let Some(current) = &self.current
+            && !self.config.can_track(current)
+        {
+            log::info!("{current} is no longer configured, stopping it");
+            self.switch(None)?;
+        }
+        self.notify(Notification::ConfigReloaded);
+        Ok(())
+    }
+
+    fn switch(&mut self, activity: Option<Activity>) -> Result<()> {
+        self.activity_log
+            .log(Event::SwitchActivity(activity.clone()))
+            .context("failed to write to the time log")?;
+        self.current = activity.clone();
+        self.started = SystemTime::now();
+        self.idle_time = Duration::ZERO;
+        self.notify(Notification::Switched(activity));
+        Ok(())
+    }
+
+    fn status(&self) -> Status {
+        Status::new(
+            self.current.clone(),
+            self.active_duration(),
+            self.idle_since.is_some(),
+            self.presence,
+        )
+    }
+
+    /// Push a notification to all subscribed clients
+    fn notify(&self, notification: Notification) {
+        // sending only fails when there are no subscribers
+        let _ = self.notify_tx.send(IpcResponse::Notification {
+            notification,
+            status: self.status(),
+        });
+    }
+
+    fn started_secs(&self) -> u64 {
+        self.started
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    /// Time spent on the current activity, excluding idle periods
+    fn active_duration(&self) -> Duration {
+        let mut idle_time = self.idle_time;
+        if let Some(idle_since) = self.idle_since {
+            idle_time += Duration::from_secs(
+                get_unix_time().saturating_sub(idle_since.max(self.started_secs())),
+            );
+        }
+        self.started
+            .elapsed()
+            .expect("time went backwards")
+            .saturating_sub(idle_time)
+    }
+
+    fn respond(&mut self, msg: IpcRequest) -> IpcResponse {
+        self.handle_msg(msg).unwrap_or_else(|e| {
+            log::error!("failed to handle request: {e:?}");
+            IpcResponse::error(ErrorKind::Internal, format!("{e:#}"))
+        })
+    }
+
     fn handle_msg(&mut self, msg: IpcRequest) -> Result<IpcResponse> {
         Ok(match msg {
             IpcRequest::GetActivities => IpcResponse::Activities(self.config.activities.clone()),
             IpcRequest::Switch(new) => {
                 if new != self.current {
                     if let Some(new_activity) = new {
-                        if self.config.activities.contains(&new_activity) {
+                        if self.config.can_track(&new_activity) {
                             log::info!("switching to {}", new_activity);
-                            self.activity_log
-                                .log(Event::SwitchActivity(Some(new_activity.clone())))
-                                .unwrap();
-                            self.current = Some(new_activity);
-                            self.started = SystemTime::now();
+                            self.switch(Some(new_activity))?;
                         } else {
                             log::error!("unknown activity: {}", new_activity);
+                            return Ok(IpcResponse::error(
+                                ErrorKind::UnknownActivity,
+                                format!(
+                                    "'{new_activity}' is not a configured activity or a parent of one"
+                                ),
+                            ));
                         }
                     } else {
                         log::info!("switching to no activity");
-                        self.activity_log.log(Event::SwitchActivity(None)).unwrap();
-                        self.current = None;
-                        self.started = SystemTime::now();
+                        self.switch(None)?;
                     }
                 }
                 IpcResponse::Empty
             }
-            IpcRequest::Status => IpcResponse::Status(Status::new(
-                self.current.clone(),
-                self.started.elapsed().expect("time went backwards"),
-            )),
+            IpcRequest::Status => IpcResponse::Status(self.status()),
+            IpcRequest::AddActivity(activity) => {
+                if self.config.is_configured(&activity) {
+                    return Ok(IpcResponse::error(
+                        ErrorKind::ActivityExists,
+                        format!("'{activity}' is already a configured activity"),
+                    ));
+                }
+                let mut config = self.config.clone();
+                // restoring an archived activity keeps its metadata
+                let info = match config.archived.iter().position(|a| a.activity == activity) {
+                    Some(i) => config.archived.remove(i),
+                    // only existing activities may have a legacy key
+                    None => match Activity::new(activity.to_string()) {
+                        Ok(activity) => ActivityInfo::new(activity),
+                        Err(e) => {
+                            return Ok(IpcResponse::error(
+                                ErrorKind::InvalidRequest,
+                                format!("{e:#}"),
+                            ));
+                        }
+                    },
+                };
+                config.activities.push(info);
+                if let Err(e) = config.validate() {
+                    return Ok(IpcResponse::error(
+                        ErrorKind::InvalidRequest,
+                        format!("{e:#}"),
+                    ));
+                }
+                config.save_activities()?;
+                log::info!("added activity {activity}");
+                self.config = config;
+                IpcResponse::Empty
+            }
+            IpcRequest::RemoveActivity(activity) => self.remove_activity(activity, false)?,
+            IpcRequest::ArchiveActivity(activity) => self.remove_activity(activity, true)?,
+            IpcRequest::ReloadConfig => match self.reload_config() {
+                Ok(()) => IpcResponse::Empty,
+                Err(e) => IpcResponse::error(ErrorKind::InvalidConfig, format!("{e:#}")),
+            },
+            IpcRequest::Subscribe => IpcResponse::error(
+                ErrorKind::InvalidRequest,
+                "subscribe is handled per connection",
+            ),
         })
     }
+
+    /// Remove an activity from the config or archive it, stopping it if it is running
+    fn remove_activity(&mut self, activity: Activity, archive: bool) -> Result<IpcResponse> {
+        let mut config = self.config.clone();
+        let configured = config
+            .activities
+            .iter()
+            .position(|a| a.activity == activity);
+        let archived = config.archived.iter().position(|a| a.activity == activity);
+        let info = match (configured, archived) {
+            (Some(i), _) => config.activities.remove(i),
+            (None, Some(i)) if !archive => config.archived.remove(i),
+            _ => {
+                return Ok(IpcResponse::error(
+                    ErrorKind::UnknownActivity,
+                    format!("'{activity}' is not a configured activity"),
+                ));
+            }
+        };
+        if archive {
+            config.archived.push(info);
+        }
+        if let Err(e) = config.validate() {
+            return Ok(IpcResponse::error(
+                ErrorKind::InvalidRequest,
+                format!("{e:#}"),
+            ));
+        }
+        config.save_activities()?;
+        if archive {
+            log::info!("archived activity {activity}");
+        } else {
+            log::info!("removed activity {activity}");
+        }
+        self.config = config;
+        if let Some(current) = &self.current
+            && !self.config.can_track(current)
+        {
+            self.switch(None)?;
+        }
+        Ok(IpcResponse::Empty)
+    }
+}
+
+/// Forward notifications to a persistent connection until it is closed
+async fn forward_notifications(
+    mut notify_rx: broadcast::Receiver<IpcResponse>,
+    tx: mpsc::Sender<IpcResponse>,
+) {
+    loop {
+        let received = tokio::select! {
+            received = notify_rx.recv() => received,
+            _ = tx.closed() => break,
+        };
+        match received {
+            Ok(notification) => {
+                if tx.send(notification).await.is_err() {
+                    break;
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                log::warn!("subscriber missed {n} notifications");
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+/// Send on every change of the config file, also when an editor replaces the file
+///
+/// A symlinked config is watched at its target as well, as found when the daemon starts
+async fn watch_config(tx: mpsc::Sender<()>) -> Result<()> {
+    let path = config_path()?;
+    let mut paths = vec![path.clone()];
+    if let Ok(target) = fs::canonicalize(&path)
+        && target != path
+    {
+        paths.push(target);
+    }
+    let inotify = Inotify::init().context("failed to initialize inotify")?;
+    let mut watches = Vec::new();
+    for path in paths {
+        let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+            bail!("invalid config path '{}'", path.display());
+        };
+        let watch = inotify
+            .watches()
+            .add(dir, WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO)
+            .with_context(|| format!("failed to watch '{}'", dir.display()))?;
+        watches.push((watch, name.to_os_string()));
+    }
+    let mut events = inotify.into_event_stream([0; 1024])?;
+    while let Some(event) = events.next().await {
+        let event = event?;
+        let changed = watches
+            .iter()
+            .any(|(watch, name)| event.wd == *watch && event.name.as_deref() == Some(name));
+        // a reload that is already pending also covers this change
+        if changed && let Err(mpsc::error::TrySendError::Closed(_)) = tx.try_send(()) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+enum PresenceEvent {
+    Connected,
+    Disconnected,
+    Active(u64),
+}
+
+/// Forward input activity from actived, reconnecting with backoff whenever the connection is lost
+async fn run_presence(tx: mpsc::Sender<PresenceEvent>) {
+    let mut delay = MIN_RECONNECT_DELAY;
+    loop {
+        match SocketStream::connect(ttd::activity_daemon_socket(), &[]).await {
+            Ok(mut stream) => {
+                log::info!("connected to {ACTIVITY_DAEMON_NAME}");
+                delay = MIN_RECONNECT_DELAY;
+                if tx.send(PresenceEvent::Connected).await.is_err() {
+                    return;
+                }
+                let e = loop {
+                    match stream.recv::<ActivityMessage>().await {
+                        Ok(msg) => {
+                            if tx
+                                .send(PresenceEvent::Active(msg.last_active))
+                                .await
+                                .is_err()
+                            {
+                                return;
+                            }
+                        }
+                        Err(e) => break e,
+                    }
+                };
+                log::warn!("lost connection to {ACTIVITY_DAEMON_NAME}: {e:#}");
+                if tx.send(PresenceEvent::Disconnected).await.is_err() {
+                    return;
+                }
+            }
+            Err(e) => {
+                log::warn!("{ACTIVITY_DAEMON_NAME} is not available, retrying in {delay:?}: {e:#}");
+            }
+        }
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn activity(key: &str) -> Activity {
+        Activity::new(key.to_string()).unwrap()
+    }
+
+    /// Apply a change to the config parsed from `input`, and write it back to `input`
+    fn rewrite(input: &str, change: impl FnOnce(&mut Config)) -> String {
+        let mut config: Config = toml::from_str(input).unwrap();
+        change(&mut config);
+        let mut doc = input.parse::<DocumentMut>().unwrap();
+        config.update_activities(&mut doc);
+        let output = doc.to_string();
+        assert_eq!(toml::from_str::<Config>(&output).unwrap(), config);
+        output
+    }
+
+    fn archive(config: &mut Config, key: &str) {
+        let i = config
+            .activities
+            .iter()
+            .position(|a| a.activity.as_str() == key)
+            .unwrap();
+        let info = config.activities.remove(i);
+        config.archived.push(info);
+    }
+
+    fn restore(config: &mut Config, key: &str) {
+        let i = config
+            .archived
+            .iter()
+            .position(|a| a.activity.as_str() == key)
+            .unwrap();
+        let info = config.archived.remove(i);
+        config.activities.push(info);
+    }
+
+    fn add(config: &mut Config, key: &str) {
+        config.activities.push(ActivityInfo::new(activity(key)));
+    }
+
+    #[test]
+    fn comment_after_an_entry_moves_with_it() {
+        let input = r#"activities = [
+  { key = "work" }, # paid
+  { key = "play" },
+]
+"#;
+        let archived = rewrite(input, |c| archive(c, "work"));
+        assert_eq!(
+            archived,
+            r#"activities = [
+  { key = "play" },
+]
+archived = [
+  { key = "work" }, # paid
+]
+"#
+        );
+        assert_eq!(
+            rewrite(&archived, |c| restore(c, "work")),
+            r#"activities = [
+  { key = "play" },
+  { key = "work" }, # paid
+]
+"#
+        );
+    }
+
+    #[test]
+    fn comments_of_the_brackets_and_the_last_entry() {
+        let input = r#"activities = [ # all of them
+  { key = "work" }, # paid
+  # fun
+  { key = "play" }, # free
+]
+idle_timeout = 5
+"#;
+        assert_eq!(
+            rewrite(input, |c| archive(c, "play")),
+            r#"activities = [ # all of them
+  { key = "work" }, # paid
+]
+idle_timeout = 5
+archived = [
+  # fun
+  { key = "play" }, # free
+]
+"#
+        );
+        assert_eq!(
+            rewrite(input, |c| archive(c, "work")),
+            r#"activities = [ # all of them
+  # fun
+  { key = "play" }, # free
+]
+idle_timeout = 5
+archived = [
+  { key = "work" }, # paid
+]
+"#
+        );
+    }
+
+    #[test]
+    fn new_entries_are_indented_like_the_last() {
+        let input = r#"activities = [
+  { key = "work" }, # paid
+  { key = "play" },
+]
+"#;
+        assert_eq!(
+            rewrite(input, |c| add(c, "study")),
+            r#"activities = [
+  { key = "work" }, # paid
+  { key = "play" },
+  { key = "study" },
+]
+"#
+        );
+    }
+
+    #[test]
+    fn single_line_array() {
+        let input = "activities = [{ key = \"work\" }, { key = \"play\" }]\n";
+        let archived = rewrite(input, |c| archive(c, "work"));
+        assert_eq!(
+            archived,
+            "activities = [{ key = \"play\" }]\narchived = [{ key = \"work\" }]\n"
+        );
+        assert_eq!(
+            rewrite(&archived, |c| restore(c, "work")),
+            "activities = [{ key = \"play\" }, { key = \"work\" }]\n"
+        );
+    }
+
+    #[test]
+    fn array_of_tables_keeps_comments() {
+        let input = r#"[[activities]]
+key = "work" # paid
+
+# fun
+[[activities]]
+key = "play"
+"#;
+        assert_eq!(
+            rewrite(input, |c| archive(c, "play")),
+            r#"[[activities]]
+key = "work" # paid
+
+# fun
+[[archived]]
+key = "play"
+"#
+        );
+    }
+
+    #[test]
+    fn empty_array_of_tables_keeps_the_key() {
+        let input = r#"[[activities]]
+key = "work"
+
+[[activities]]
+key = "play"
+"#;
+        assert_eq!(
+            rewrite(input, |c| c.activities.clear()),
+            "activities = []\n"
+        );
+        assert_eq!(
+            rewrite(input, |c| {
+                archive(c, "work");
+                archive(c, "play");
+            }),
+            r#"activities = []
+[[archived]]
+key = "work"
+
+[[archived]]
+key = "play"
+"#
+        );
+    }
 }
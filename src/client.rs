@@ -0,0 +1,151 @@
+use crate::{
+    ActivityInfo, CAP_SUBSCRIBE, ErrorKind, IpcResponse, Notification, Status,
+    protocol::{CAP_PERSISTENT, ConnectionClosed, Frame, IncompatibleProtocol},
+};
+use std::{
+    fmt::{self, Display},
+    time::Duration,
+};
+
+#[cfg(feature = "client")]
+mod async_client;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+
+#[cfg(feature = "client")]
+pub use async_client::{Client, Subscription};
+
+/// Capabilities requested by both clients, persistent connections are required
+const CAPABILITIES: &[&str] = &[CAP_SUBSCRIBE, CAP_PERSISTENT];
+
+/// How long a subscription keeps trying to reconnect after losing the daemon, e.g. on a restart
+const RECONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const RECONNECT_INTERVAL: Duration = Duration::from_millis(500);
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The daemon is not running, or its socket cannot be reached
+    Unavailable(anyhow::Error),
+    /// The daemon speaks a different protocol version
+    IncompatibleProtocol(IncompatibleProtocol),
+    /// The daemon does not support a capability that is required
+    Unsupported(&'static str),
+    /// The connection was lost and could not be restored
+    Disconnected,
+    /// The daemon refused the request
+    Daemon { kind: ErrorKind, message: String },
+    /// The daemon sent a response that does not belong to the request
+    UnexpectedResponse,
+    /// Any other failure while talking to the daemon
+    Protocol(anyhow::Error),
+}
+
+impl Error {
+    pub(crate) fn from_connect(e: anyhow::Error) -> Self {
+        match e.downcast::<IncompatibleProtocol>() {
+            Ok(e) => Self::IncompatibleProtocol(e),
+            Err(e) => Self::Unavailable(e),
+        }
+    }
+
+    pub(crate) fn from_request(e: anyhow::Error) -> Self {
+        if e.is::<ConnectionClosed>() {
+            Self::Disconnected
+        } else {
+            Self::Protocol(e)
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Unavailable(_) => write!(f, "the ttd daemon is not available, is it running?"),
+            Self::IncompatibleProtocol(e) => write!(f, "{e}"),
+            Self::Unsupported(capability) => {
+                write!(f, "the daemon does not support '{capability}'")
+            }
+            Self::Disconnected => write!(f, "lost connection to the daemon"),
+            Self::Daemon { kind, message } => write!(f, "{kind}: {message}"),
+            Self::UnexpectedResponse => write!(f, "unexpected response from the daemon"),
+            Self::Protocol(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Unavailable(e) => Some(e.as_ref()),
+            Self::Protocol(e) => e.source(),
+            _ => None,
+        }
+    }
+}
+
+/// A change pushed by the daemon, with the status after it
+#[derive(Debug, Clone)]
+pub struct Update {
+    /// `None` for the status sent when (re)subscribing
+    pub notification: Option<Notification>,
+    pub status: Status,
+}
+
+impl Update {
+    /// The status sent when (re)subscribing
+    fn current(status: Status) -> Self {
+        Self {
+            notification: None,
+            status,
+        }
+    }
+
+    /// A frame received on a subscription, which only carries notifications
+    fn from_frame(frame: Frame<IpcResponse>) -> Result<Self> {
+        match frame {
+            Frame::Notification(IpcResponse::Notification {
+                notification,
+                status,
+            }) => Ok(Self {
+                notification: Some(notification),
+                status,
+            }),
+            _ => Err(Error::UnexpectedResponse),
+        }
+    }
+}
+
+// The response handling below is shared by the async and the blocking client, which only
+// differ in how they send requests.
+
+/// Turn error responses into [`Error::Daemon`]
+fn check_response(resp: IpcResponse) -> Result<IpcResponse> {
+    match resp {
+        IpcResponse::Error { kind, message } => Err(Error::Daemon { kind, message }),
+        resp => Ok(resp),
+    }
+}
+
+/// The response to a status request, or to subscribing
+fn expect_status(resp: IpcResponse) -> Result<Status> {
+    match check_response(resp)? {
+        IpcResponse::Status(status) => Ok(status),
+        _ => Err(Error::UnexpectedResponse),
+    }
+}
+
+fn expect_activities(resp: IpcResponse) -> Result<Vec<ActivityInfo>> {
+    match check_response(resp)? {
+        IpcResponse::Activities(activities) => Ok(activities),
+        _ => Err(Error::UnexpectedResponse),
+    }
+}
+
+fn expect_empty(resp: IpcResponse) -> Result<()> {
+    match check_response(resp)? {
+        IpcResponse::Empty => Ok(()),
+        _ => Err(Error::UnexpectedResponse),
+    }
+}
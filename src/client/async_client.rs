@@ -0,0 +1,186 @@
+use super::{
+    CAPABILITIES, Error, RECONNECT_INTERVAL, RECONNECT_TIMEOUT, Result, Update, check_response,
+    expect_activities, expect_empty, expect_status,
+};
+use crate::{
+    Activity, ActivityInfo, CAP_SUBSCRIBE, IpcRequest, IpcResponse, Status,
+    async_socket::{CAP_PERSISTENT, Connection, SocketStream},
+    socket_path,
+};
+use std::{
+    path::{Path, PathBuf},
+    time::Instant,
+};
+use tokio::{sync::mpsc, task::JoinHandle};
+
+/// Connection to the daemon, which is restored when the daemon closes it
+pub struct Client {
+    path: PathBuf,
+    connection: Option<Connection<IpcRequest, IpcResponse>>,
+}
+
+impl Client {
+    /// Connect to the daemon of the current user
+    pub async fn connect() -> Result<Self> {
+        Self::connect_to(socket_path()).await
+    }
+
+    pub async fn connect_to(path: PathBuf) -> Result<Self> {
+        let connection = open(&path).await?;
+        Ok(Self {
+            path,
+            connection: Some(connection),
+        })
+    }
+
+    /// Send a request, turning error responses into [`Error::Daemon`]
+    pub async fn request(&mut self, req: IpcRequest) -> Result<IpcResponse> {
+        // reconnect once, the daemon may have been restarted since the last request
+        let resp = match self.try_request(req.clone()).await {
+            Err(Error::Disconnected) => self.try_request(req).await?,
+            resp => resp?,
+        };
+        check_response(resp)
+    }
+
+    async fn try_request(&mut self, req: IpcRequest) -> Result<IpcResponse> {
+        let mut connection = match self.connection.take() {
+            Some(connection) => connection,
+            None => open(&self.path).await?,
+        };
+        let resp = connection.request(req).await.map_err(Error::from_request)?;
+        // the connection is dropped on errors, since it may be halfway a message
+        self.connection = Some(connection);
+        Ok(resp)
+    }
+
+    pub async fn status(&mut self) -> Result<Status> {
+        expect_status(self.request(IpcRequest::Status).await?)
+    }
+
+    pub async fn activities(&mut self) -> Result<Vec<ActivityInfo>> {
+        expect_activities(self.request(IpcRequest::GetActivities).await?)
+    }
+
+    pub async fn switch(&mut self, activity: Activity) -> Result<()> {
+        self.switch_to(Some(activity)).await
+    }
+
+    /// Stop tracking the current activity
+    pub async fn stop(&mut self) -> Result<()> {
+        self.switch_to(None).await
+    }
+
+    async fn switch_to(&mut self, activity: Option<Activity>) -> Result<()> {
+        self.request_empty(IpcRequest::Switch(activity)).await
+    }
+
+    /// Add an activity to the config, or restore an archived one
+    pub async fn add_activity(&mut self, activity: Activity) -> Result<()> {
+        self.request_empty(IpcRequest::AddActivity(activity)).await
+    }
+
+    pub async fn remove_activity(&mut self, activity: Activity) -> Result<()> {
+        self.request_empty(IpcRequest::RemoveActivity(activity))
+            .await
+    }
+
+    /// Hide an activity from the list of activities, while keeping it in the config
+    pub async fn archive_activity(&mut self, activity: Activity) -> Result<()> {
+        self.request_empty(IpcRequest::ArchiveActivity(activity))
+            .await
+    }
+
+    /// Load the config file again, returns [`Error::Daemon`] when it is not valid
+    pub async fn reload_config(&mut self) -> Result<()> {
+        self.request_empty(IpcRequest::ReloadConfig).await
+    }
+
+    async fn request_empty(&mut self, req: IpcRequest) -> Result<()> {
+        expect_empty(self.request(req).await?)
+    }
+
+    /// Receive the current status followed by an update on every change, on a separate connection
+    pub async fn subscribe(&self) -> Result<Subscription> {
+        let (connection, status) = open_subscription(&self.path).await?;
+        let (tx, rx) = mpsc::channel(16);
+        let _ = tx.send(Ok(Update::current(status))).await;
+        let task = tokio::spawn(run_subscription(self.path.clone(), connection, tx));
+        Ok(Subscription { rx, task })
+    }
+}
+
+/// Updates pushed by the daemon, the subscription is restored when the daemon restarts
+pub struct Subscription {
+    rx: mpsc::Receiver<Result<Update>>,
+    task: JoinHandle<()>,
+}
+
+impl Subscription {
+    /// Wait for the next update, this is cancel safe
+    pub async fn next(&mut self) -> Result<Update> {
+        self.rx.recv().await.unwrap_or(Err(Error::Disconnected))
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn open(path: &Path) -> Result<Connection<IpcRequest, IpcResponse>> {
+    let stream = SocketStream::connect(path.to_path_buf(), CAPABILITIES)
+        .await
+        .map_err(Error::from_connect)?;
+    Connection::new(stream).map_err(|_| Error::Unsupported(CAP_PERSISTENT))
+}
+
+async fn open_subscription(path: &Path) -> Result<(Connection<IpcRequest, IpcResponse>, Status)> {
+    let mut connection = open(path).await?;
+    if !connection.stream().has_capability(CAP_SUBSCRIBE) {
+        return Err(Error::Unsupported(CAP_SUBSCRIBE));
+    }
+    let resp = connection
+        .request(IpcRequest::Subscribe)
+        .await
+        .map_err(Error::from_request)?;
+    Ok((connection, expect_status(resp)?))
+}
+
+/// Forward updates until the subscription is dropped, reconnecting when the connection is lost
+async fn run_subscription(
+    path: PathBuf,
+    mut connection: Connection<IpcRequest, IpcResponse>,
+    tx: mpsc::Sender<Result<Update>>,
+) {
+    loop {
+        let update = match connection.recv().await.map_err(Error::from_request) {
+            Ok(frame) => Update::from_frame(frame),
+            Err(Error::Disconnected) => match reconnect(&path).await {
+                Some((new, status)) => {
+                    connection = new;
+                    Ok(Update::current(status))
+                }
+                None => Err(Error::Disconnected),
+            },
+            Err(e) => Err(e),
+        };
+        let failed = update.is_err();
+        if tx.send(update).await.is_err() || failed {
+            break;
+        }
+    }
+}
+
+async fn reconnect(path: &Path) -> Option<(Connection<IpcRequest, IpcResponse>, Status)> {
+    let started = Instant::now();
+    while started.elapsed() < RECONNECT_TIMEOUT {
+        tokio::time::sleep(RECONNECT_INTERVAL).await;
+        match open_subscription(path).await {
+            Ok(subscription) => return Some(subscription),
+            Err(e) => log::debug!("failed to reconnect: {e}"),
+        }
+    }
+    None
+}
@@ -0,0 +1,270 @@
+use super::{
+    CAPABILITIES, Error, RECONNECT_INTERVAL, RECONNECT_TIMEOUT, Result, Update, check_response,
+    expect_activities, expect_empty, expect_status,
+};
+use crate::{
+    Activity, ActivityInfo, CAP_SUBSCRIBE, IpcRequest, IpcResponse, Status,
+    protocol::{
+        CAP_PERSISTENT, DEFAULT_TIMEOUT, Frame, Hello, IncompatibleProtocol, MAX_MESSAGE_SIZE,
+        PROTOCOL_VERSION, Request, decode, encode, io_error, message_length, negotiate,
+    },
+    socket_path,
+};
+use anyhow::{Context, bail};
+use serde::{Serialize, de::DeserializeOwned};
+use std::{
+    io::{Read, Write},
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+    thread,
+    time::Instant,
+};
+
+/// Blocking connection to the daemon, which is restored when the daemon closes it
+pub struct Client {
+    path: PathBuf,
+    connection: Option<Connection>,
+}
+
+impl Client {
+    /// Connect to the daemon of the current user
+    pub fn connect() -> Result<Self> {
+        Self::connect_to(socket_path())
+    }
+
+    pub fn connect_to(path: PathBuf) -> Result<Self> {
+        let connection = Connection::open(&path)?;
+        Ok(Self {
+            path,
+            connection: Some(connection),
+        })
+    }
+
+    /// Send a request, turning error responses into [`Error::Daemon`]
+    pub fn request(&mut self, req: IpcRequest) -> Result<IpcResponse> {
+        // reconnect once, the daemon may have been restarted since the last request
+        let resp = match self.try_request(req.clone()) {
+            Err(Error::Disconnected) => self.try_request(req)?,
+            resp => resp?,
+        };
+        check_response(resp)
+    }
+
+    fn try_request(&mut self, req: IpcRequest) -> Result<IpcResponse> {
+        let mut connection = match self.connection.take() {
+            Some(connection) => connection,
+            None => Connection::open(&self.path)?,
+        };
+        let resp = connection.request(req).map_err(Error::from_request)?;
+        // the connection is dropped on errors, since it may be halfway a message
+        self.connection = Some(connection);
+        Ok(resp)
+    }
+
+    pub fn status(&mut self) -> Result<Status> {
+        expect_status(self.request(IpcRequest::Status)?)
+    }
+
+    pub fn activities(&mut self) -> Result<Vec<ActivityInfo>> {
+        expect_activities(self.request(IpcRequest::GetActivities)?)
+    }
+
+    pub fn switch(&mut self, activity: Activity) -> Result<()> {
+        self.switch_to(Some(activity))
+    }
+
+    /// Stop tracking the current activity
+    pub fn stop(&mut self) -> Result<()> {
+        self.switch_to(None)
+    }
+
+    fn switch_to(&mut self, activity: Option<Activity>) -> Result<()> {
+        self.request_empty(IpcRequest::Switch(activity))
+    }
+
+    /// Add an activity to the config, or restore an archived one
+    pub fn add_activity(&mut self, activity: Activity) -> Result<()> {
+        self.request_empty(IpcRequest::AddActivity(activity))
+    }
+
+    pub fn remove_activity(&mut self, activity: Activity) -> Result<()> {
+        self.request_empty(IpcRequest::RemoveActivity(activity))
+    }
+
+    /// Hide an activity from the list of activities, while keeping it in the config
+    pub fn archive_activity(&mut self, activity: Activity) -> Result<()> {
+        self.request_empty(IpcRequest::ArchiveActivity(activity))
+    }
+
+    /// Load the config file again, returns [`Error::Daemon`] when it is not valid
+    pub fn reload_config(&mut self) -> Result<()> {
+        self.request_empty(IpcRequest::ReloadConfig)
+    }
+
+    fn request_empty(&mut self, req: IpcRequest) -> Result<()> {
+        expect_empty(self.request(req)?)
+    }
+
+    /// Receive the current status followed by an update on every change, on a separate connection
+    pub fn subscribe(&self) -> Result<Subscription> {
+        let (connection, status) = open_subscription(&self.path)?;
+        Ok(Subscription {
+            path: self.path.clone(),
+            connection: Some(connection),
+            initial: Some(Update::current(status)),
+        })
+    }
+}
+
+/// Updates pushed by the daemon, the subscription is restored when the daemon restarts,
+/// iteration ends after the first error
+pub struct Subscription {
+    path: PathBuf,
+    connection: Option<Connection>,
+    initial: Option<Update>,
+}
+
+impl Iterator for Subscription {
+    type Item = Result<Update>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(update) = self.initial.take() {
+            return Some(Ok(update));
+        }
+        let connection = self.connection.as_mut()?;
+        let update = match connection.recv().map_err(Error::from_request) {
+            Ok(frame) => Update::from_frame(frame),
+            Err(Error::Disconnected) => match reconnect(&self.path) {
+                Some((new, status)) => {
+                    *connection = new;
+                    Ok(Update::current(status))
+                }
+                None => Err(Error::Disconnected),
+            },
+            Err(e) => Err(e),
+        };
+        if update.is_err() {
+            self.connection = None;
+        }
+        Some(update)
+    }
+}
+
+/// A persistent connection over a blocking socket, see `SocketStream::serve` for the protocol
+struct Connection {
+    stream: UnixStream,
+    capabilities: Vec<String>,
+    next_id: u64,
+}
+
+impl Connection {
+    fn open(path: &Path) -> Result<Self> {
+        let stream = UnixStream::connect(path)
+            .with_context(|| format!("failed to connect to socket at '{path:?}'"))
+            .map_err(Error::Unavailable)?;
+        let mut connection = Self {
+            stream,
+            capabilities: Vec::new(),
+            next_id: 0,
+        };
+        connection.capabilities = connection.hello().map_err(Error::from_connect)?;
+        if !connection.has_capability(CAP_PERSISTENT) {
+            return Err(Error::Unsupported(CAP_PERSISTENT));
+        }
+        Ok(connection)
+    }
+
+    fn has_capability(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+
+    /// Exchange hellos, returns the capabilities supported by both sides
+    fn hello(&mut self) -> anyhow::Result<Vec<String>> {
+        self.stream.set_read_timeout(Some(DEFAULT_TIMEOUT))?;
+        self.stream.set_write_timeout(Some(DEFAULT_TIMEOUT))?;
+        self.send(&Hello::new(CAPABILITIES))?;
+        let hello: Hello = self
+            .recv()
+            .context("no protocol hello received, the server may use an older protocol")?;
+        if hello.version != PROTOCOL_VERSION {
+            return Err(IncompatibleProtocol {
+                version: hello.version,
+            }
+            .into());
+        }
+        Ok(negotiate(CAPABILITIES, &hello.capabilities))
+    }
+
+    fn send<T: Serialize>(&mut self, msg: &T) -> anyhow::Result<()> {
+        let bytes = encode(msg)?;
+        let length = message_length(&bytes)?;
+        self.stream
+            .write_all(&length.to_be_bytes())
+            .map_err(|e| io_error(e, "failed to write message length"))?;
+        self.stream
+            .write_all(&bytes)
+            .map_err(|e| io_error(e, "failed to write message"))?;
+        self.stream
+            .flush()
+            .map_err(|e| io_error(e, "failed to flush stream"))
+    }
+
+    fn recv<T: DeserializeOwned>(&mut self) -> anyhow::Result<T> {
+        let mut length = [0; 4];
+        self.stream
+            .read_exact(&mut length)
+            .map_err(|e| io_error(e, "failed to read message length"))?;
+        let length = u32::from_be_bytes(length);
+        if length > MAX_MESSAGE_SIZE {
+            bail!("message of {length} bytes is too large");
+        }
+        let mut buf = vec![0; length as usize];
+        self.stream
+            .read_exact(&mut buf)
+            .map_err(|e| io_error(e, "failed to read message"))?;
+        decode(&buf)
+    }
+
+    fn request(&mut self, body: IpcRequest) -> anyhow::Result<IpcResponse> {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.send(&Request { id, body })?;
+        // no notifications are pushed before subscribing, so the next response is ours
+        loop {
+            if let Frame::Response { id: i, body } = self.recv()?
+                && i == id
+            {
+                return Ok(body);
+            }
+        }
+    }
+}
+
+fn open_subscription(path: &Path) -> Result<(Connection, Status)> {
+    let mut connection = Connection::open(path)?;
+    if !connection.has_capability(CAP_SUBSCRIBE) {
+        return Err(Error::Unsupported(CAP_SUBSCRIBE));
+    }
+    let resp = connection
+        .request(IpcRequest::Subscribe)
+        .map_err(Error::from_request)?;
+    let status = expect_status(resp)?;
+    // wait for notifications indefinitely
+    connection
+        .stream
+        .set_read_timeout(None)
+        .map_err(|e| Error::Protocol(e.into()))?;
+    Ok((connection, status))
+}
+
+fn reconnect(path: &Path) -> Option<(Connection, Status)> {
+    let started = Instant::now();
+    while started.elapsed() < RECONNECT_TIMEOUT {
+        thread::sleep(RECONNECT_INTERVAL);
+        match open_subscription(path) {
+            Ok(subscription) => return Some(subscription),
+            Err(e) => log::debug!("failed to reconnect: {e}"),
+        }
+    }
+    None
+}
@@ -1,11 +1,19 @@
+#[cfg(feature = "ipc")]
 pub mod async_socket;
+#[cfg(any(feature = "client", feature = "blocking"))]
+pub mod client;
+#[cfg(any(feature = "ipc", feature = "blocking"))]
+pub mod protocol;
+pub mod search;
+#[cfg(feature = "log")]
+pub mod timeline;
 use anyhow::{Context, Result, anyhow, bail};
 use serde::{Deserialize, Serialize};
 use std::{
     fmt::{self, Display},
     fs::{self, File, OpenOptions},
     io::{Read, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
     str::FromStr,
     time::{Duration, SystemTime},
 };
@@ -20,6 +28,14 @@ pub fn activity_daemon_socket() -> PathBuf {
         .with_extension("sock")
 }
 
+/// Access configuration of actived, which runs as root and is shared by all users
+pub fn activity_daemon_config() -> PathBuf {
+    PathBuf::from("/etc")
+        .join(APP_NAME)
+        .join(ACTIVITY_DAEMON_NAME)
+        .with_extension("toml")
+}
+
 pub fn get_unix_time() -> u64 {
     SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
@@ -58,6 +74,8 @@ impl FromStr for TimedEvent {
 pub enum Event {
     Power(bool),
     SwitchActivity(Option<Activity>),
+    /// Start (`true`) or end (`false`) of a period without user input
+    Idle(bool),
 }
 
 impl Display for Event {
@@ -79,6 +97,14 @@ impl Display for Event {
                     write!(f, "-")
                 }
             }
+            Self::Idle(idle) => {
+                write!(f, "I ")?;
+                if *idle {
+                    write!(f, "start")
+                } else {
+                    write!(f, "end")
+                }
+            }
         }
     }
 }
@@ -103,22 +129,41 @@ impl FromStr for Event {
                 let activity = if rest == "-" {
                     None
                 } else {
-                    Some(Activity::new(rest.to_string())?)
+                    Some(Activity::new_legacy(rest.to_string())?)
                 };
                 Ok(Self::SwitchActivity(activity))
             }
+            "I" => {
+                let idle = match rest {
+                    "start" => true,
+                    "end" => false,
+                    _ => bail!("invalid idle state: '{}'", rest),
+                };
+                Ok(Self::Idle(idle))
+            }
             _ => bail!("invalid event kind: '{}'", kind),
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+/// An activity key, where `:` separates a parent from its children, e.g. `work:ttd:review`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Activity {
     key: String,
 }
 
 impl Activity {
     pub fn new(key: String) -> Result<Self> {
+        let activity = Self::new_legacy(key)?;
+        if activity.is_legacy() {
+            bail!("invalid activity: '{}', it has an empty segment", activity);
+        }
+        Ok(activity)
+    }
+
+    /// Also accepts keys with empty segments like `work:` or `a::b`, which were valid before
+    /// activities became a hierarchy, these are a single leaf without parents
+    pub fn new_legacy(key: String) -> Result<Self> {
         if key.is_empty()
             || !key
                 .chars()
@@ -128,6 +173,69 @@ impl Activity {
         }
         Ok(Self { key })
     }
+
+    pub fn as_str(&self) -> &str {
+        &self.key
+    }
+
+    /// Whether the key has an empty segment, see [`Activity::new_legacy`]
+    pub fn is_legacy(&self) -> bool {
+        self.key.split(':').any(str::is_empty)
+    }
+
+    /// The parts of the key from the top level down
+    pub fn segments(&self) -> impl Iterator<Item = &str> {
+        let legacy = self.is_legacy();
+        self.key.split(move |c| c == ':' && !legacy)
+    }
+
+    /// The last part of the key, e.g. `review` for `work:ttd:review`
+    pub fn leaf(&self) -> &str {
+        self.segments().last().unwrap_or_default()
+    }
+
+    /// The number of segments, top level activities have a depth of one
+    pub fn depth(&self) -> usize {
+        self.segments().count()
+    }
+
+    pub fn parent(&self) -> Option<Activity> {
+        if self.is_legacy() {
+            return None;
+        }
+        let (parent, _) = self.key.rsplit_once(':')?;
+        Some(Self {
+            key: parent.to_string(),
+        })
+    }
+
+    /// The activity itself followed by all its parents, up to the top level
+    pub fn ancestors(&self) -> impl Iterator<Item = Activity> {
+        std::iter::successors(Some(self.clone()), Activity::parent)
+    }
+
+    /// Whether this is `other` or one of its children
+    pub fn is_within(&self, other: &Activity) -> bool {
+        if self.is_legacy() {
+            return self == other;
+        }
+        self.key
+            .strip_prefix(&other.key)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with(':'))
+    }
+}
+
+// ordered by segment, such that children directly follow their parent
+impl Ord for Activity {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.segments().cmp(other.segments())
+    }
+}
+
+impl PartialOrd for Activity {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
 }
 
 impl Display for Activity {
@@ -136,6 +244,62 @@ impl Display for Activity {
     }
 }
 
+/// A configured activity with its optional metadata, only the key is written to the time log
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ActivityInfo {
+    #[serde(flatten)]
+    pub activity: Activity,
+    /// Name to show instead of the key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Other names to switch to the activity with, e.g. `rev` for `work:ttd:review`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Color for status bars and other frontends, e.g. `#ff8800`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project: Option<String>,
+    #[serde(default)]
+    pub billable: bool,
+    /// Rate per hour of billable time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hourly_rate: Option<f64>,
+}
+
+impl ActivityInfo {
+    /// An activity without metadata
+    pub fn new(activity: Activity) -> Self {
+        Self {
+            activity,
+            name: None,
+            aliases: Vec::new(),
+            description: None,
+            color: None,
+            client: None,
+            project: None,
+            billable: false,
+            hourly_rate: None,
+        }
+    }
+
+    /// The name if set, otherwise the key
+    pub fn display_name(&self) -> &str {
+        self.name.as_deref().unwrap_or(self.activity.as_str())
+    }
+
+    /// The amount billed for the given seconds, if the activity is billable and has a rate
+    pub fn amount(&self, seconds: i64) -> Option<f64> {
+        self.hourly_rate
+            .filter(|_| self.billable)
+            .map(|rate| rate * seconds as f64 / 3600.0)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ActivityMessage {
     pub last_active: u64,
@@ -146,15 +310,82 @@ pub enum IpcRequest {
     Status,
     Switch(Option<Activity>),
     GetActivities,
+    /// Keep the connection open and receive a notification on every change
+    Subscribe,
+    /// Add an activity to the config, or restore an archived one
+    AddActivity(Activity),
+    /// Remove an activity from the config
+    RemoveActivity(Activity),
+    /// Hide an activity from the list of activities, while keeping it in the config
+    ArchiveActivity(Activity),
+    /// Load the config file again
+    ReloadConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum IpcResponse {
     Empty,
     Status(Status),
-    Activities(Vec<Activity>),
+    Activities(Vec<ActivityInfo>),
+    Error {
+        kind: ErrorKind,
+        message: String,
+    },
+    /// Pushed to subscribers, with the status after the change
+    Notification {
+        notification: Notification,
+        status: Status,
+    },
+}
+
+impl IpcResponse {
+    pub fn error(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self::Error {
+            kind,
+            message: message.into(),
+        }
+    }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Notification {
+    Switched(Option<Activity>),
+    IdleStarted,
+    IdleEnded,
+    ConfigReloaded,
+    /// The config file changed but could not be loaded, the previous config remains in use
+    ConfigError(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ErrorKind {
+    /// The activity is not in the configured activities
+    UnknownActivity,
+    /// The activity is already in the configured activities
+    ActivityExists,
+    /// The request could not be read or is not valid
+    InvalidRequest,
+    /// The config file could not be loaded
+    InvalidConfig,
+    /// The daemon failed to handle the request
+    Internal,
+}
+
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnknownActivity => write!(f, "unknown activity"),
+            Self::ActivityExists => write!(f, "activity exists"),
+            Self::InvalidRequest => write!(f, "invalid request"),
+            Self::InvalidConfig => write!(f, "invalid config"),
+            Self::Internal => write!(f, "internal error"),
+        }
+    }
+}
+
+/// Capability of the daemon and clients to push and receive notifications
+pub const CAP_SUBSCRIBE: &str = "subscribe";
+
 pub fn socket_path() -> PathBuf {
     dirs::runtime_dir()
         .expect("No runtime directory found!")
@@ -167,14 +398,58 @@ pub fn socket_path() -> PathBuf {
 pub struct Status {
     activity: Option<Activity>,
     duration: Duration,
+    idle: bool,
+    presence: Presence,
 }
 
 impl Status {
-    pub fn new(activity: Option<Activity>, duration: Duration) -> Self {
-        Self { activity, duration }
+    pub fn new(
+        activity: Option<Activity>,
+        duration: Duration,
+        idle: bool,
+        presence: Presence,
+    ) -> Self {
+        Self {
+            activity,
+            duration,
+            idle,
+            presence,
+        }
+    }
+
+    pub fn activity(&self) -> Option<&Activity> {
+        self.activity.as_ref()
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.idle
+    }
+
+    pub fn presence(&self) -> Presence {
+        self.presence
+    }
+
+    /// The status after some time has passed, the duration does not increase while idle
+    pub fn advanced(&self, elapsed: Duration) -> Self {
+        let mut status = self.clone();
+        if !self.idle {
+            status.duration += elapsed;
+        }
+        status
     }
 }
 
+/// Whether the daemon receives input activity from the activity daemon
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Presence {
+    Connected,
+    Disconnected,
+}
+
 fn format_duration(f: &mut fmt::Formatter, duration: Duration) -> fmt::Result {
     let mut secs = duration.as_secs();
     let days = secs / 86_400;
@@ -192,6 +467,15 @@ fn format_duration(f: &mut fmt::Formatter, duration: Duration) -> fmt::Result {
     write!(f, "{:02}:{:02}", minutes, secs)
 }
 
+/// Displays a duration as `[Nd ][HH:]MM:SS`
+pub struct DisplayDuration(pub Duration);
+
+impl Display for DisplayDuration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        format_duration(f, self.0)
+    }
+}
+
 impl Display for Status {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         if let Some(activity) = &self.activity {
@@ -201,18 +485,41 @@ impl Display for Status {
         }
         write!(f, " ")?;
         format_duration(f, self.duration)?;
+        if self.idle {
+            write!(f, " (idle)")?;
+        }
+        if self.presence == Presence::Disconnected {
+            write!(f, " (no presence data)")?;
+        }
         Ok(())
     }
 }
 
-fn activity_log_path() -> Result<PathBuf> {
+/// The config file of the daemon, the config dir is created if needed
+pub fn config_path() -> Result<PathBuf> {
+    let dir = dirs::config_dir().context("no config dir")?.join(APP_NAME);
+    if !dir.exists() {
+        fs::create_dir_all(&dir).context("failed to create config dir")?;
+    }
+    Ok(dir.join("config.toml"))
+}
+
+fn data_dir() -> Result<PathBuf> {
     let path = dirs::data_local_dir()
         .context("no data local dir")?
         .join(APP_NAME);
     if !path.exists() {
-        fs::create_dir_all(path.parent().unwrap()).context("failed to create log dir")?;
+        fs::create_dir_all(&path).context("failed to create log dir")?;
     }
-    Ok(path.join("time_log"))
+    Ok(path)
+}
+
+fn activity_log_path() -> Result<PathBuf> {
+    Ok(data_dir()?.join("time_log"))
+}
+
+fn checkpoint_path() -> Result<PathBuf> {
+    Ok(data_dir()?.join("checkpoint"))
 }
 
 pub struct ActivityLog {
@@ -222,18 +529,88 @@ pub struct ActivityLog {
 impl ActivityLog {
     pub fn load() -> Result<Self> {
         let path = activity_log_path().context("failed to open time log file")?;
+        Self::repair(&path)?;
+        let unclean_shutdown = Self::unclean_shutdown(&path)?;
         let file = OpenOptions::new()
             .create(true)
             .append(true)
             .open(path)
             .context("failed to open time log file")?;
         let mut log = Self { file };
+        if let Some(stopped) = unclean_shutdown {
+            log::warn!("time log was not closed, recovering power off at {stopped}");
+            log.log_at(stopped, Event::Power(false))?;
+        }
         log.log(Event::Power(true))?;
         Ok(log)
     }
 
+    /// Drop a last line that was only partly written, e.g. on a power loss, and make sure the
+    /// log ends with a newline such that the next event starts on its own line
+    fn repair(path: &Path) -> Result<()> {
+        if !path.exists() {
+            return Ok(());
+        }
+        let contents = fs::read(path).context("failed to read time log")?;
+        let end = contents.trim_ascii_end().len();
+        if end == 0 {
+            return Ok(());
+        }
+        let start = contents[..end]
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(0, |i| i + 1);
+        let last = String::from_utf8_lossy(&contents[start..end]);
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(path)
+            .context("failed to open time log file")?;
+        if last.parse::<TimedEvent>().is_err() {
+            log::warn!("dropping incomplete last line of the time log: '{last}'");
+            file.set_len(start as u64)
+                .context("failed to truncate time log")?;
+        } else if !contents.ends_with(b"\n") {
+            writeln!(file).context("failed to write time log")?;
+        }
+        Ok(())
+    }
+
+    /// If the last run did not log a power off, returns the last time it was known to be running
+    fn unclean_shutdown(path: &Path) -> Result<Option<u64>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let contents = fs::read_to_string(path).context("failed to read time log")?;
+        let Some(line) = contents.lines().rev().find(|l| !l.trim().is_empty()) else {
+            return Ok(None);
+        };
+        let last: TimedEvent = line.parse().context("failed to parse time log")?;
+        if matches!(last.event, Event::Power(false)) {
+            return Ok(None);
+        }
+        let last_timestamp = last.timestamp.max(0) as u64;
+        let checkpoint = fs::read_to_string(checkpoint_path()?)
+            .ok()
+            .and_then(|c| c.trim().parse::<u64>().ok());
+        Ok(Some(
+            checkpoint.map_or(last_timestamp, |c| c.max(last_timestamp)),
+        ))
+    }
+
+    /// Record that the daemon is still running, used to recover from an unclean shutdown
+    pub fn checkpoint(&self) -> Result<()> {
+        let path = checkpoint_path()?;
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, get_unix_time().to_string()).context("failed to write checkpoint")?;
+        fs::rename(&tmp_path, &path).context("failed to write checkpoint")?;
+        Ok(())
+    }
+
     pub fn log(&mut self, event: Event) -> Result<()> {
-        let timestamp = get_unix_time();
+        self.log_at(get_unix_time(), event)
+    }
+
+    pub fn log_at(&mut self, timestamp: u64, event: Event) -> Result<()> {
         writeln!(self.file, "{timestamp} {event}")?;
 
         Ok(())
@@ -266,8 +643,16 @@ impl ActivityRead {
         self.file
             .read_to_string(&mut contents)
             .context("failed to read time log")?;
-        contents
-            .lines()
+        let mut lines: Vec<&str> = contents.lines().collect();
+        // the daemon may be writing the last line, or was stopped while writing it
+        if let Some(last) = lines.last()
+            && last.parse::<TimedEvent>().is_err()
+        {
+            log::warn!("ignoring incomplete last line of the time log: '{last}'");
+            lines.pop();
+        }
+        lines
+            .into_iter()
             .map(str::parse)
             .collect::<Result<Vec<TimedEvent>>>()
             .context("failed to parse time log")
@@ -0,0 +1,117 @@
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use std::{
+    fmt::{self, Display},
+    io,
+    time::Duration,
+};
+
+/// Version of the protocol, connections between different versions are refused
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Largest message that is sent or accepted, limits the buffer allocated for a peer
+pub const MAX_MESSAGE_SIZE: u32 = 1 << 20;
+
+/// Default time to write a message, or to read the rest of a message once it started
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Capability for connections carrying many requests, see `SocketStream::serve`
+pub const CAP_PERSISTENT: &str = "persistent";
+
+/// First message on every connection
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Hello {
+    pub version: u32,
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+}
+
+impl Hello {
+    pub(crate) fn new(capabilities: &[&str]) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            capabilities: capabilities.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct IncompatibleProtocol {
+    pub version: u32,
+}
+
+impl Display for IncompatibleProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "incompatible protocol v{}, expected v{}",
+            self.version, PROTOCOL_VERSION
+        )
+    }
+}
+
+impl std::error::Error for IncompatibleProtocol {}
+
+/// The peer closed the connection
+#[derive(Debug)]
+pub struct ConnectionClosed;
+
+impl Display for ConnectionClosed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "connection closed by peer")
+    }
+}
+
+impl std::error::Error for ConnectionClosed {}
+
+/// A request on a persistent connection, tagged with an id chosen by the client
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Request<T> {
+    pub id: u64,
+    pub body: T,
+}
+
+/// A message from the server on a persistent connection
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Frame<T> {
+    /// The answer to the request with the same id
+    Response { id: u64, body: T },
+    /// Pushed by the server, not tied to a request
+    Notification(T),
+}
+
+pub(crate) fn io_error(e: io::Error, context: &'static str) -> anyhow::Error {
+    match e.kind() {
+        io::ErrorKind::UnexpectedEof
+        | io::ErrorKind::BrokenPipe
+        | io::ErrorKind::ConnectionReset => ConnectionClosed.into(),
+        _ => anyhow::Error::new(e).context(context),
+    }
+}
+
+/// Messages are sent as a big endian `u32` length followed by the MessagePack encoding
+pub(crate) fn encode<T: Serialize>(msg: &T) -> Result<Vec<u8>> {
+    // encode structs as maps, such that fields can be added later
+    rmp_serde::to_vec_named(msg).context("failed to serialize message")
+}
+
+pub(crate) fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+    rmp_serde::from_slice(bytes).context("failed to deserialize message")
+}
+
+/// Length prefix of an encoded message, fails if it exceeds [`MAX_MESSAGE_SIZE`]
+pub(crate) fn message_length(bytes: &[u8]) -> Result<u32> {
+    u32::try_from(bytes.len())
+        .ok()
+        .filter(|l| *l <= MAX_MESSAGE_SIZE)
+        .ok_or_else(|| anyhow!("message of {} bytes is too large", bytes.len()))
+}
+
+pub(crate) fn negotiate(ours: &[&str], theirs: &[String]) -> Vec<String> {
+    theirs
+        .iter()
+        .filter(|c| ours.contains(&c.as_str()))
+        .cloned()
+        .collect()
+}
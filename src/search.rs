@@ -0,0 +1,210 @@
+use crate::{Activity, ActivityInfo};
+
+/// The result of looking up an activity by what the user typed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Match {
+    /// A key, an alias, or the only activity matching the query
+    Found(Activity),
+    /// Several activities match, the best matches first
+    Ambiguous(Vec<Activity>),
+    NotFound,
+}
+
+/// Find the activity meant by a query, in order of preference:
+///
+/// 1. a key or alias that equals the query
+/// 2. a key, the last part of a key, an alias or a name that starts with the query,
+///    e.g. `rev` for `work:ttd:review`
+/// 3. one of those containing the characters of the query in order, e.g. `wtr` for `work:ttd:review`
+///
+/// Parents of the activities are included, since they can be switched to as well,
+/// only exact matches are case sensitive
+pub fn find_activity(query: &str, activities: &[ActivityInfo]) -> Match {
+    let candidates = with_parents(activities);
+    if let Some(info) = candidates
+        .iter()
+        .find(|info| info.activity.as_str() == query || info.aliases.iter().any(|a| a == query))
+    {
+        return Match::Found(info.activity.clone());
+    }
+
+    let query = query.to_lowercase();
+    let prefixed: Vec<Activity> = candidates
+        .iter()
+        .filter(|info| names(info).any(|name| name.to_lowercase().starts_with(&query)))
+        .map(|info| info.activity.clone())
+        .collect();
+    if !prefixed.is_empty() {
+        return Match::from_candidates(prefixed);
+    }
+
+    let mut fuzzy: Vec<(usize, Activity)> = candidates
+        .iter()
+        .filter_map(|info| {
+            let score = names(info)
+                .filter_map(|name| fuzzy_score(&query, &name.to_lowercase()))
+                .min()?;
+            Some((score, info.activity.clone()))
+        })
+        .collect();
+    // a stable sort keeps the order of the config for equal scores
+    fuzzy.sort_by_key(|(score, _)| *score);
+    Match::from_candidates(fuzzy.into_iter().map(|(_, activity)| activity).collect())
+}
+
+impl Match {
+    fn from_candidates(mut candidates: Vec<Activity>) -> Self {
+        match candidates.len() {
+            0 => Self::NotFound,
+            1 => Self::Found(candidates.remove(0)),
+            _ => Self::Ambiguous(candidates),
+        }
+    }
+}
+
+/// The activities followed by the parents that are not configured themselves
+fn with_parents(activities: &[ActivityInfo]) -> Vec<ActivityInfo> {
+    let mut all = activities.to_vec();
+    for info in activities {
+        for parent in info.activity.ancestors().skip(1) {
+            if !all.iter().any(|a| a.activity == parent) {
+                all.push(ActivityInfo::new(parent));
+            }
+        }
+    }
+    all
+}
+
+/// Everything a query can match on
+fn names(info: &ActivityInfo) -> impl Iterator<Item = &str> {
+    std::iter::once(info.activity.as_str())
+        .chain(Some(info.activity.leaf()))
+        .chain(info.aliases.iter().map(String::as_str))
+        .chain(info.name.as_deref())
+}
+
+/// The length of the shortest part of `text` that contains the characters of `query` in order
+fn fuzzy_score(query: &str, text: &str) -> Option<usize> {
+    let text: Vec<char> = text.chars().collect();
+    let first = query.chars().next()?;
+    let mut best: Option<usize> = None;
+    for start in (0..text.len()).filter(|&i| text[i] == first) {
+        let mut chars = query.chars().peekable();
+        let mut end = start;
+        while let (Some(&c), Some(&t)) = (chars.peek(), text.get(end)) {
+            if t == c {
+                chars.next();
+            }
+            end += 1;
+        }
+        if chars.peek().is_none() {
+            let length = end - start;
+            best = Some(best.map_or(length, |best| best.min(length)));
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn activity(key: &str) -> Activity {
+        Activity::new(key.to_string()).unwrap()
+    }
+
+    fn activities() -> Vec<ActivityInfo> {
+        let mut review = ActivityInfo::new(activity("work:ttd:review"));
+        review.aliases = vec!["cr".to_string()];
+        let mut reading = ActivityInfo::new(activity("reading"));
+        reading.name = Some("Books".to_string());
+        vec![
+            ActivityInfo::new(activity("work")),
+            review,
+            ActivityInfo::new(activity("play")),
+            reading,
+        ]
+    }
+
+    fn find(query: &str) -> Match {
+        find_activity(query, &activities())
+    }
+
+    fn found(key: &str) -> Match {
+        Match::Found(activity(key))
+    }
+
+    #[test]
+    fn exact_key_or_alias() {
+        assert_eq!(find("work"), found("work"));
+        assert_eq!(find("cr"), found("work:ttd:review"));
+    }
+
+    #[test]
+    fn parents_can_be_found() {
+        assert_eq!(find("work:ttd"), found("work:ttd"));
+        assert_eq!(find("ttd"), found("work:ttd"));
+    }
+
+    #[test]
+    fn unique_prefix() {
+        assert_eq!(find("rev"), found("work:ttd:review"));
+        assert_eq!(find("pl"), found("play"));
+        assert_eq!(find("boo"), found("reading"));
+    }
+
+    #[test]
+    fn prefix_is_case_insensitive() {
+        assert_eq!(find("PL"), found("play"));
+        assert_eq!(find("Rev"), found("work:ttd:review"));
+    }
+
+    #[test]
+    fn ambiguous_prefix() {
+        assert_eq!(
+            find("r"),
+            Match::Ambiguous(vec![activity("work:ttd:review"), activity("reading")])
+        );
+        assert_eq!(
+            find("wo"),
+            Match::Ambiguous(vec![
+                activity("work"),
+                activity("work:ttd:review"),
+                activity("work:ttd")
+            ])
+        );
+    }
+
+    #[test]
+    fn fuzzy() {
+        assert_eq!(find("wtr"), found("work:ttd:review"));
+        assert_eq!(find("ply"), found("play"));
+    }
+
+    #[test]
+    fn fuzzy_prefers_the_shortest_match() {
+        // both contain 'a' followed by 'y', closest together in 'play'
+        let activities = [
+            ActivityInfo::new(activity("administrator-duty")),
+            ActivityInfo::new(activity("play")),
+        ];
+        assert_eq!(
+            find_activity("ay", &activities),
+            Match::Ambiguous(vec![activity("play"), activity("administrator-duty")])
+        );
+    }
+
+    #[test]
+    fn not_found() {
+        assert_eq!(find("zzz"), Match::NotFound);
+        assert_eq!(find_activity("work", &[]), Match::NotFound);
+    }
+
+    #[test]
+    fn fuzzy_score_is_the_shortest_window() {
+        assert_eq!(fuzzy_score("wtr", "work:ttd:review"), Some(10));
+        assert_eq!(fuzzy_score("ab", "a-b-ab"), Some(2));
+        assert_eq!(fuzzy_score("ba", "ab"), None);
+        assert_eq!(fuzzy_score("", "ab"), None);
+    }
+}
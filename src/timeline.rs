@@ -0,0 +1,577 @@
+use crate::{Activity, ActivityRead, Event, TimedEvent, get_unix_time};
+use anyhow::Result;
+use jiff::{Span, Timestamp, ToSpan, civil::Date, tz::TimeZone};
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display},
+    str::FromStr,
+};
+
+/// A closed interval of time spent on a single activity, excluding idle periods
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+    pub activity: Activity,
+    pub start: i64,
+    pub end: i64,
+}
+
+impl Session {
+    pub fn duration(&self) -> i64 {
+        self.end - self.start
+    }
+
+    /// Clip the session to the window `[start, end)`, returns `None` if they do not overlap
+    pub fn clip(&self, start: i64, end: i64) -> Option<Session> {
+        let clipped = Session {
+            activity: self.activity.clone(),
+            start: self.start.max(start),
+            end: self.end.min(end),
+        };
+        (clipped.start < clipped.end).then_some(clipped)
+    }
+}
+
+/// Reconstruct the sessions from a sequence of logged events
+///
+/// A session ends when switching activities, on power events and when becoming idle,
+/// the session that is still running after the last event is closed at `now`
+pub fn sessions(events: &[TimedEvent], now: i64) -> Vec<Session> {
+    let mut sessions = Vec::new();
+    let mut current: Option<Activity> = None;
+    let mut idle = false;
+    let mut since = 0;
+
+    let mut close = |current: &Option<Activity>, idle: bool, since: i64, end: i64| {
+        if let Some(activity) = current
+            && !idle
+            && end > since
+        {
+            sessions.push(Session {
+                activity: activity.clone(),
+                start: since,
+                end,
+            });
+        }
+    };
+
+    for event in events {
+        let timestamp = event.timestamp;
+        match &event.event {
+            Event::SwitchActivity(activity) => {
+                close(&current, idle, since, timestamp);
+                current = activity.clone();
+                since = timestamp;
+            }
+            Event::Idle(true) => {
+                close(&current, idle, since, timestamp);
+                idle = true;
+            }
+            Event::Idle(false) => {
+                idle = false;
+                since = timestamp;
+            }
+            Event::Power(_) => {
+                // the daemon never has an activity directly after starting or stopping
+                close(&current, idle, since, timestamp);
+                current = None;
+                idle = false;
+            }
+        }
+    }
+    close(&current, idle, since, now);
+    sessions
+}
+
+/// Clip the sessions to the window `[start, end)`, dropping those outside of it
+pub fn clip_sessions(sessions: &[Session], start: i64, end: i64) -> Vec<Session> {
+    sessions.iter().filter_map(|s| s.clip(start, end)).collect()
+}
+
+/// The activity that was running when the daemon last stopped, and when it stopped
+pub fn last_activity(events: &[TimedEvent]) -> Option<(Activity, i64)> {
+    let mut current: Option<Activity> = None;
+    let mut last = None;
+    let mut prev_timestamp = None;
+    for event in events {
+        match &event.event {
+            Event::SwitchActivity(activity) => current = activity.clone(),
+            // a regular start, nothing was running
+            Event::Power(true) if current.is_none() => {}
+            Event::Power(on) => {
+                // without a power off the daemon stopped uncleanly after the previous event
+                let stopped = if *on {
+                    prev_timestamp
+                } else {
+                    Some(event.timestamp)
+                };
+                last = current.take().zip(stopped);
+            }
+            Event::Idle(_) => {}
+        }
+        prev_timestamp = Some(event.timestamp);
+    }
+    current.zip(prev_timestamp).or(last)
+}
+
+/// Selects activities, either a single one or a parent with everything below it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ActivityFilter {
+    Exact(Activity),
+    /// Written as `work:*`, matches `work` and all its children
+    Subtree(Activity),
+}
+
+impl ActivityFilter {
+    pub fn matches(&self, activity: &Activity) -> bool {
+        match self {
+            Self::Exact(exact) => activity == exact,
+            Self::Subtree(parent) => activity.is_within(parent),
+        }
+    }
+}
+
+impl FromStr for ActivityFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s.strip_suffix(":*") {
+            Some(parent) => Self::Subtree(Activity::new(parent.to_string())?),
+            None => Self::Exact(Activity::new(s.to_string())?),
+        })
+    }
+}
+
+impl Display for ActivityFilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Exact(activity) => write!(f, "{activity}"),
+            Self::Subtree(parent) => write!(f, "{parent}:*"),
+        }
+    }
+}
+
+/// All sessions in the time log, ordered by start time
+#[derive(Debug, Clone, Default)]
+pub struct Timeline {
+    sessions: Vec<Session>,
+}
+
+impl Timeline {
+    /// Load the timeline from the time log, the running session is included up to now
+    pub fn load() -> Result<Self> {
+        let events = ActivityRead::load()?.read()?;
+        Ok(Self::from_events(&events, get_unix_time() as i64))
+    }
+
+    pub fn from_events(events: &[TimedEvent], now: i64) -> Self {
+        Self::from_sessions(sessions(events, now))
+    }
+
+    pub fn from_sessions(mut sessions: Vec<Session>) -> Self {
+        sessions.sort_by_key(|s| s.start);
+        Self { sessions }
+    }
+
+    pub fn sessions(&self) -> &[Session] {
+        &self.sessions
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Session> {
+        self.sessions.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+
+    /// Sessions within the window `[start, end)`, clipped to it
+    pub fn range(&self, start: i64, end: i64) -> Timeline {
+        Self {
+            sessions: clip_sessions(&self.sessions, start, end),
+        }
+    }
+
+    /// Sessions of the activities selected by the filter
+    pub fn filter(&self, filter: &ActivityFilter) -> Timeline {
+        Self {
+            sessions: self
+                .iter()
+                .filter(|s| filter.matches(&s.activity))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Total duration of all sessions in seconds
+    pub fn total(&self) -> i64 {
+        self.iter().map(Session::duration).sum()
+    }
+
+    /// Total duration in seconds per activity
+    pub fn by_activity(&self) -> BTreeMap<Activity, i64> {
+        let mut totals = BTreeMap::new();
+        for session in self {
+            *totals.entry(session.activity.clone()).or_insert(0) += session.duration();
+        }
+        totals
+    }
+
+    /// Total duration in seconds per activity and each of its parents
+    ///
+    /// A parent includes the time of all its children, e.g. `work` includes `work:ttd`,
+    /// iterating the result visits every parent directly before its children
+    pub fn rollup(&self) -> BTreeMap<Activity, i64> {
+        let mut totals = BTreeMap::new();
+        for (activity, duration) in self.by_activity() {
+            for ancestor in activity.ancestors() {
+                *totals.entry(ancestor).or_insert(0) += duration;
+            }
+        }
+        totals
+    }
+
+    /// Group the sessions by day, sessions spanning midnight are split
+    pub fn by_day(&self, tz: &TimeZone) -> Result<BTreeMap<Date, Timeline>> {
+        self.group_by(tz, |date| date, 1.day())
+    }
+
+    /// Group the sessions by week, keyed on the Monday that starts the week
+    pub fn by_week(&self, tz: &TimeZone) -> Result<BTreeMap<Date, Timeline>> {
+        self.group_by(
+            tz,
+            |date| {
+                date.checked_sub(i64::from(date.weekday().to_monday_zero_offset()).days())
+                    .unwrap()
+            },
+            1.week(),
+        )
+    }
+
+    fn group_by(
+        &self,
+        tz: &TimeZone,
+        period_start: impl Fn(Date) -> Date,
+        period: Span,
+    ) -> Result<BTreeMap<Date, Timeline>> {
+        let mut groups: BTreeMap<Date, Timeline> = BTreeMap::new();
+        for session in self {
+            let start = Timestamp::from_second(session.start)?.to_zoned(tz.clone());
+            let mut date = period_start(start.date());
+            loop {
+                let start = date.to_zoned(tz.clone())?.timestamp().as_second();
+                if start >= session.end {
+                    break;
+                }
+                let next = date.checked_add(period)?;
+                let end = next.to_zoned(tz.clone())?.timestamp().as_second();
+                if let Some(clipped) = session.clip(start, end) {
+                    groups.entry(date).or_default().sessions.push(clipped);
+                }
+                date = next;
+            }
+        }
+        Ok(groups)
+    }
+}
+
+impl<'a> IntoIterator for &'a Timeline {
+    type Item = &'a Session;
+    type IntoIter = std::slice::Iter<'a, Session>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl IntoIterator for Timeline {
+    type Item = Session;
+    type IntoIter = std::vec::IntoIter<Session>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.sessions.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events(lines: &[&str]) -> Vec<TimedEvent> {
+        lines.iter().map(|line| line.parse().unwrap()).collect()
+    }
+
+    fn activity(key: &str) -> Activity {
+        Activity::new(key.to_string()).unwrap()
+    }
+
+    fn session(key: &str, start: i64, end: i64) -> Session {
+        Session {
+            activity: activity(key),
+            start,
+            end,
+        }
+    }
+
+    #[test]
+    fn sessions_end_on_switch_and_power_off() {
+        let events = events(&[
+            "0 P on",
+            "10 A work",
+            "30 A play",
+            "50 A -",
+            "60 A work",
+            "70 P off",
+        ]);
+        assert_eq!(
+            sessions(&events, 100),
+            [
+                session("work", 10, 30),
+                session("play", 30, 50),
+                session("work", 60, 70)
+            ]
+        );
+    }
+
+    #[test]
+    fn running_session_ends_now() {
+        let events = events(&["0 P on", "10 A work"]);
+        assert_eq!(sessions(&events, 25), [session("work", 10, 25)]);
+    }
+
+    #[test]
+    fn idle_time_is_excluded() {
+        let events = events(&["0 P on", "10 A work", "20 I start", "40 I end", "50 P off"]);
+        assert_eq!(
+            sessions(&events, 100),
+            [session("work", 10, 20), session("work", 40, 50)]
+        );
+    }
+
+    #[test]
+    fn switch_while_idle_starts_when_input_returns() {
+        let events = events(&["0 P on", "10 A work", "20 I start", "30 A play", "40 I end"]);
+        assert_eq!(
+            sessions(&events, 60),
+            [session("work", 10, 20), session("play", 40, 60)]
+        );
+    }
+
+    #[test]
+    fn power_off_ends_idle() {
+        let events = events(&[
+            "0 P on",
+            "10 A work",
+            "20 I start",
+            "30 P off",
+            "40 P on",
+            "50 A play",
+        ]);
+        assert_eq!(
+            sessions(&events, 60),
+            [session("work", 10, 20), session("play", 50, 60)]
+        );
+    }
+
+    #[test]
+    fn unclean_power_on_ends_session() {
+        let events = events(&["0 P on", "10 A work", "20 P on", "30 A play"]);
+        assert_eq!(
+            sessions(&events, 40),
+            [session("work", 10, 20), session("play", 30, 40)]
+        );
+    }
+
+    #[test]
+    fn sessions_are_not_split_at_midnight() {
+        // 2025-01-01T00:00:00Z
+        let midnight = 1735689600;
+        let events = events(&[&format!("{} A work", midnight - 60)]);
+        assert_eq!(
+            sessions(&events, midnight + 60),
+            [session("work", midnight - 60, midnight + 60)]
+        );
+    }
+
+    #[test]
+    fn last_activity_after_power_off() {
+        let events = events(&["0 P on", "10 A work", "20 P off", "30 P on"]);
+        assert_eq!(last_activity(&events), Some((activity("work"), 20)));
+    }
+
+    #[test]
+    fn last_activity_after_unclean_power_on() {
+        let events = events(&["0 P on", "10 A work", "20 I start", "30 P on"]);
+        assert_eq!(last_activity(&events), Some((activity("work"), 20)));
+    }
+
+    #[test]
+    fn last_activity_still_running() {
+        let events = events(&["0 P on", "10 A work", "15 I start"]);
+        assert_eq!(last_activity(&events), Some((activity("work"), 15)));
+    }
+
+    #[test]
+    fn no_last_activity_when_stopped() {
+        let events = events(&["0 P on", "10 A work", "20 A -", "30 P off", "40 P on"]);
+        assert_eq!(last_activity(&events), None);
+        assert_eq!(last_activity(&[]), None);
+    }
+
+    /// Central European time, which switches to summer time on 2025-03-30 at 02:00
+    fn cet() -> TimeZone {
+        TimeZone::posix("CET-1CEST,M3.5.0,M10.5.0/3").unwrap()
+    }
+
+    fn at(tz: &TimeZone, datetime: &str) -> i64 {
+        datetime
+            .parse::<jiff::civil::DateTime>()
+            .unwrap()
+            .to_zoned(tz.clone())
+            .unwrap()
+            .timestamp()
+            .as_second()
+    }
+
+    fn date(date: &str) -> Date {
+        date.parse().unwrap()
+    }
+
+    fn totals(groups: BTreeMap<Date, Timeline>) -> Vec<(Date, i64)> {
+        groups
+            .into_iter()
+            .map(|(date, timeline)| (date, timeline.total()))
+            .collect()
+    }
+
+    #[test]
+    fn by_day_splits_at_midnight() {
+        let tz = TimeZone::UTC;
+        let timeline = Timeline::from_sessions(vec![
+            session(
+                "work",
+                at(&tz, "2025-01-01T10:00"),
+                at(&tz, "2025-01-01T11:00"),
+            ),
+            session(
+                "play",
+                at(&tz, "2025-01-01T23:00"),
+                at(&tz, "2025-01-02T01:30"),
+            ),
+        ]);
+        let days = timeline.by_day(&tz).unwrap();
+        assert_eq!(
+            days[&date("2025-01-02")].sessions(),
+            [session(
+                "play",
+                at(&tz, "2025-01-02T00:00"),
+                at(&tz, "2025-01-02T01:30")
+            )]
+        );
+        assert_eq!(
+            totals(days),
+            [(date("2025-01-01"), 2 * 3600), (date("2025-01-02"), 5400)]
+        );
+    }
+
+    #[test]
+    fn by_day_splits_at_local_midnight_across_dst() {
+        let tz = cet();
+        // the day of the change only has 23 hours
+        let timeline = Timeline::from_sessions(vec![
+            session(
+                "work",
+                at(&tz, "2025-03-29T23:00"),
+                at(&tz, "2025-03-30T04:00"),
+            ),
+            session(
+                "work",
+                at(&tz, "2025-03-30T23:00"),
+                at(&tz, "2025-03-31T01:00"),
+            ),
+        ]);
+        assert_eq!(
+            totals(timeline.by_day(&tz).unwrap()),
+            [
+                (date("2025-03-29"), 3600),
+                (date("2025-03-30"), 3 * 3600 + 3600),
+                (date("2025-03-31"), 3600)
+            ]
+        );
+    }
+
+    #[test]
+    fn by_week_starts_on_monday() {
+        let tz = cet();
+        // Sunday to Monday, in the week of the change to summer time
+        let timeline = Timeline::from_sessions(vec![
+            session(
+                "work",
+                at(&tz, "2025-03-24T09:00"),
+                at(&tz, "2025-03-24T10:00"),
+            ),
+            session(
+                "work",
+                at(&tz, "2025-03-30T23:30"),
+                at(&tz, "2025-03-31T00:30"),
+            ),
+        ]);
+        assert_eq!(
+            totals(timeline.by_week(&tz).unwrap()),
+            [
+                (date("2025-03-24"), 3600 + 1800),
+                (date("2025-03-31"), 1800)
+            ]
+        );
+    }
+
+    #[test]
+    fn rollup_includes_children_in_parents() {
+        let timeline = Timeline::from_sessions(vec![
+            session("work", 0, 10),
+            session("work:ttd:review", 10, 12),
+            session("play", 12, 15),
+            session("work:ttd", 15, 20),
+            session("work2", 20, 21),
+        ]);
+        let rollup: Vec<(String, i64)> = timeline
+            .rollup()
+            .into_iter()
+            .map(|(activity, duration)| (activity.to_string(), duration))
+            .collect();
+        // parents are directly followed by their children
+        assert_eq!(
+            rollup,
+            [
+                ("play".to_string(), 3),
+                ("work".to_string(), 17),
+                ("work:ttd".to_string(), 7),
+                ("work:ttd:review".to_string(), 2),
+                ("work2".to_string(), 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn legacy_keys_are_not_rolled_up() {
+        let legacy = Activity::new_legacy("work:".to_string()).unwrap();
+        let timeline = Timeline::from_events(&events(&["0 A work:", "10 A work:ttd"]), 15);
+        assert_eq!(
+            timeline.rollup().into_iter().collect::<Vec<_>>(),
+            [
+                (activity("work"), 5),
+                (activity("work:ttd"), 5),
+                (legacy, 10)
+            ]
+        );
+    }
+
+    #[test]
+    fn subtree_filter() {
+        let filter: ActivityFilter = "work:*".parse().unwrap();
+        assert!(filter.matches(&activity("work")));
+        assert!(filter.matches(&activity("work:ttd:review")));
+        assert!(!filter.matches(&activity("work2")));
+        let filter: ActivityFilter = "work".parse().unwrap();
+        assert!(!filter.matches(&activity("work:ttd")));
+    }
+}